@@ -0,0 +1,8 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate rust_network;
+
+fuzz_target!(|data: &[u8]| {
+    rust_network::fuzz::decode_checksum(data);
+});
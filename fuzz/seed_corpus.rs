@@ -0,0 +1,69 @@
+extern crate rust_network;
+
+use std::fs;
+use std::io::prelude::*;
+use rust_network::config::{PhysicsConfig, ControlsConfig};
+use rust_network::simulation::Simulation;
+use rust_network::vec::Vec3;
+use rust_network::scene;
+use rust_network::input::{self, Input, Grab};
+use rust_network::lockstep::{self, InputSync, Lockstep, Start};
+use rust_network::desync::{self, Checksum};
+
+fn write(target: &str, seeds: &[Vec<u8>]) {
+    let dir = format!("corpus/{}", target);
+    fs::create_dir_all(&dir).unwrap();
+    for (i, seed) in seeds.iter().enumerate() {
+        let mut file = fs::File::create(format!("{}/seed-{:03}", dir, i)).unwrap();
+        file.write_all(seed).unwrap();
+    }
+    println!("Wrote {} seeds for {}.", seeds.len(), target);
+}
+
+// Runs the server's scene for a while and writes what it would have sent as the seed
+// corpus of every target.
+fn main() {
+    let mut simulation = Simulation::init(&PhysicsConfig::default());
+    simulation.create_cube(10.0, Vec3::new(0.5, 0.5, 0.5), Vec3::new(0.0, 1.0, 0.0));
+    for n in 0..100 {
+        simulation.create_cube(0.1, Vec3::new(0.5, 0.5, 0.5), Vec3::new(((n/10)*2) as f32, 3.0, ((n%10)*2) as f32));
+    }
+
+    let mut packets = vec![simulation.serialize(true), simulation.serialize(false)]; // Paused
+    let mut checksums = Vec::new();
+    simulation.toggle_pause();
+    for tick in 0..300 {
+        if tick % 30 == 0 {
+            packets.push(simulation.serialize(tick % 60 == 0));
+        }
+        if tick % desync::INTERVAL == 0 {
+            checksums.push(Checksum::of(tick, &simulation.snapshot()).encode_message());
+        }
+        simulation.step();
+    }
+    write("decode_snapshot", &packets);
+    write("deserialize", &packets);
+    write("decode_checksum", &checksums);
+
+    // Starts for a fresh world and a loaded one, then inputs with every kind of extra.
+    let params = PhysicsConfig::default().params();
+    let mut messages = Vec::new();
+    for state in vec![None, Some(simulation.snapshot())] {
+        let start = Start { rollback: state.is_some(), peer: 1, peers: 2, session: 0, input_delay: 4, params: params,
+                            controls: ControlsConfig::default(), scene: scene::DEFAULT.to_string(), state: state };
+        messages.push(lockstep::encode_start(&start));
+    }
+    let mut sync = Lockstep::new(1, 2, 0, 4);
+    let extras = vec![
+        Input::bits(input::UP | input::LEVITATE),
+        Input { grab: Some(Grab { body: 3, target: [1.0, 2.0, 3.0] }), ..Default::default() },
+        Input::bits(input::RELEASE),
+        Input { params: Some(params), ..Default::default() },
+        Input { time_scale: Some(0.5), run: Some(120), ..Default::default() },
+    ];
+    for input in extras {
+        sync.add_local(input);
+        messages.push(sync.schedule_local());
+    }
+    write("decode_lockstep", &messages);
+}
@@ -4,6 +4,6 @@ in vec3 ms_normal;
 uniform mat4 view, proj, model;
 out vec4 normal;
 void main() {
-    normal = model * vec4(ms_normal, 0.0);
+    normal = vec4(normalize((model * vec4(ms_normal, 0.0)).xyz), 0.0); // Scaled shapes stretch their normals.
     gl_Position = proj * view * model * vec4(ms_position, 1.0);
 }
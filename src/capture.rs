@@ -0,0 +1,184 @@
+#![allow(dead_code)]
+
+extern crate byteorder;
+
+use std;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::io::prelude::*;
+use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use byteorder::{LittleEndian, WriteBytesExt, ReadBytesExt};
+
+// Capture file layout, all little endian:
+//   header: "RNCAP" u8 version, u8 role (0 server, 1 client)
+//   record: u64 microseconds since capture start, u8 direction (0 sent, 1 received),
+//           u8 address family (4 or 6), 4 or 16 address bytes, u16 port,
+//           u32 length, length bytes of datagram
+const MAGIC: &'static [u8; 5] = b"RNCAP";
+const VERSION: u8 = 1;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Role {
+    Server,
+    Client,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+pub struct Record {
+    pub time_us: u64,
+    pub direction: Direction,
+    pub peer: SocketAddr,
+    pub data: Vec<u8>,
+}
+
+pub struct Recorder {
+    out: BufWriter<File>,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, role: Role) -> Recorder {
+        let file = File::create(path).unwrap_or_else(|e| panic!("Could not create capture {}: {}", path.display(), e));
+        let mut out = BufWriter::new(file);
+        out.write_all(MAGIC).unwrap();
+        out.write_u8(VERSION).unwrap();
+        out.write_u8(match role { Role::Server => 0, Role::Client => 1 }).unwrap();
+        println!("Recording packets to {}", path.display());
+        return Recorder { out: out, start: Instant::now() };
+    }
+
+    pub fn record(&mut self, direction: Direction, peer: SocketAddr, data: &[u8]) {
+        let elapsed = self.start.elapsed();
+        let time_us = elapsed.as_secs() * 1000000 + (elapsed.subsec_nanos() / 1000) as u64;
+        self.out.write_u64::<LittleEndian>(time_us).unwrap();
+        self.out.write_u8(match direction { Direction::Sent => 0, Direction::Received => 1 }).unwrap();
+        match peer {
+            SocketAddr::V4(v4) => {
+                self.out.write_u8(4).unwrap();
+                self.out.write_all(&v4.ip().octets()).unwrap();
+            }
+            SocketAddr::V6(v6) => {
+                self.out.write_u8(6).unwrap();
+                self.out.write_all(&v6.ip().octets()).unwrap();
+            }
+        }
+        self.out.write_u16::<LittleEndian>(peer.port()).unwrap();
+        self.out.write_u32::<LittleEndian>(data.len() as u32).unwrap();
+        self.out.write_all(data).unwrap();
+    }
+}
+
+pub struct CaptureReader {
+    input: BufReader<File>,
+    pub role: Role,
+}
+
+impl CaptureReader {
+    pub fn open(path: &Path) -> CaptureReader {
+        let file = File::open(path).unwrap_or_else(|e| panic!("Could not open capture {}: {}", path.display(), e));
+        let mut input = BufReader::new(file);
+        let mut magic = [0u8; 5];
+        input.read_exact(&mut magic).unwrap();
+        if &magic != MAGIC {
+            panic!("{} is not a packet capture", path.display());
+        }
+        let version = input.read_u8().unwrap();
+        if version != VERSION {
+            panic!("{} is capture version {}, we only read version {}", path.display(), version, VERSION);
+        }
+        let role = match input.read_u8().unwrap() { 0 => Role::Server, _ => Role::Client };
+        return CaptureReader { input: input, role: role };
+    }
+
+    fn read_record(&mut self) -> std::io::Result<Record> {
+        let time_us = try!(self.input.read_u64::<LittleEndian>());
+        let direction = match try!(self.input.read_u8()) { 0 => Direction::Sent, _ => Direction::Received };
+        let ip = match try!(self.input.read_u8()) {
+            4 => {
+                let mut o = [0u8; 4];
+                try!(self.input.read_exact(&mut o));
+                SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(o[0], o[1], o[2], o[3]), 0))
+            }
+            _ => {
+                let mut o = [0u8; 16];
+                try!(self.input.read_exact(&mut o));
+                let mut s = [0u16; 8];
+                for i in 0..8 {
+                    s[i] = ((o[i*2] as u16) << 8) | o[i*2+1] as u16;
+                }
+                SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::new(s[0], s[1], s[2], s[3], s[4], s[5], s[6], s[7]), 0, 0, 0))
+            }
+        };
+        let mut peer = ip;
+        peer.set_port(try!(self.input.read_u16::<LittleEndian>()));
+        let len = try!(self.input.read_u32::<LittleEndian>()) as usize;
+        let mut data = vec![0u8; len];
+        try!(self.input.read_exact(&mut data));
+        return Ok(Record { time_us: time_us, direction: direction, peer: peer, data: data });
+    }
+}
+
+impl Iterator for CaptureReader {
+    type Item = Record;
+
+    // A truncated final record (the recorder was killed mid write) just ends the capture.
+    fn next(&mut self) -> Option<Record> {
+        self.read_record().ok()
+    }
+}
+
+// Plays back the snapshots in a capture on their original schedule, divided by `speed`.
+// Those are the datagrams the server sent, or the ones the client received.
+pub struct Replay {
+    reader: CaptureReader,
+    speed: f32,
+    start: Instant,
+    next: Option<Record>,
+    pub finished: bool,
+}
+
+impl Replay {
+    pub fn open(path: &Path, speed: f32) -> Replay {
+        assert!(speed > 0.0 && speed.is_finite(), "Replay speed {} is not above 0", speed);
+        println!("Replaying {} at {}x speed", path.display(), speed);
+        let mut replay = Replay { reader: CaptureReader::open(path), speed: speed, start: Instant::now(), next: None, finished: false };
+        replay.advance();
+        return replay;
+    }
+
+    fn advance(&mut self) {
+        let wanted = match self.reader.role { Role::Server => Direction::Sent, Role::Client => Direction::Received };
+        self.next = None;
+        while let Some(record) = self.reader.next() {
+            if record.direction == wanted {
+                self.next = Some(record);
+                return;
+            }
+        }
+        self.finished = true;
+    }
+
+    // The next snapshot if it is due, never blocks.
+    pub fn poll(&mut self) -> Option<Vec<u8>> {
+        let due = match self.next {
+            Some(ref record) => {
+                let due_us = (record.time_us as f64 / self.speed as f64) as u64;
+                Duration::new(due_us / 1000000, ((due_us % 1000000) * 1000) as u32) <= self.start.elapsed()
+            }
+            None => false,
+        };
+        if !due {
+            return None;
+        }
+        let record = self.next.take().unwrap();
+        self.advance();
+        return Some(record.data);
+    }
+}
@@ -1,68 +1,384 @@
 extern crate gl;
 extern crate glutin;
-extern crate libc;
-extern crate ode;
 extern crate byteorder;
+extern crate rust_network;
 
 mod shader_loader;
 mod renderer;
-mod simulation;
-mod vec;
+mod picking;
 
-use std::net::UdpSocket;
+use rust_network::{vec, net, config, capture, packet, input, scene, shape, joint, collision, query,
+                   lockstep, rollback, desync, save, simulation};
 use renderer::Renderer;
 use simulation::Simulation;
+use scene::Scene;
+use joint::JointLine;
+use collision::Sparks;
+use picking::Picker;
+use save::Save;
+use config::{Config, NetMode};
+use lockstep::{Lockstep, Message, InputSync};
+use rollback::Rollback;
+use desync::{Checksum, Detector};
+use capture::{Recorder, Replay, Role, Direction};
+use std::net::SocketAddr;
+use net::NetThread;
+use std::path::Path;
+use byteorder::{LittleEndian, ReadBytesExt};
+
+// Where snapshots come from, a live server or a capture file.
+enum Source {
+    Live(NetThread, SocketAddr),
+    Replay(Replay),
+}
 
 fn main() {
     print!("Starting client . . . ");
-    let socket = UdpSocket::bind("127.0.0.1:35556").unwrap();
+    let (config, rest) = Config::from_args(std::env::args().skip(1));
+    let mut host = config.network.server_host.clone();
+    let mut replay = None;
+    let mut speed = 1.0f32;
+    let mut args = rest.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--replay" => replay = Some(args.next().expect("--replay needs a capture file")),
+            // 0 would never get to the first snapshot, below that they are all overdue at once.
+            "--speed" => speed = match args.next().and_then(|s| s.parse::<f32>().ok()) {
+                Some(s) if s > 0.0 && s.is_finite() => s,
+                _ => panic!("--speed needs a number above 0"),
+            },
+            _ => host = arg,
+        }
+    }
 
-    let mut buf = [0; 9000];
-    println!("Connecting to server.");
-    let _ = socket.send_to(&buf, "127.0.0.1:35555").unwrap();
-    //let (amt, _) = socket.recv_from(&mut buf).unwrap(); // Get the Hello back
-    //println!("Recieved {} bytes hello from server.", amt);
-    //println!("Sent {}/{}[{}%] bytes", sent, buf.len(), (sent/buf.len()) as u32);
+    let buf = [0; 9000];
+    let mut recorder = config.network.record.as_ref().map(|p| Recorder::create(Path::new(p), Role::Client));
+    let mut init = None;
+    let mut source = match replay {
+        Some(ref path) => Source::Replay(Replay::open(Path::new(path), speed)),
+        None => {
+            println!("Connecting to server {}.", host);
+            let (socket, server, init_packet) = net::connect(&host, config.network.server_port, config.network.client_port, &buf).unwrap();
+            println!("Connected to {}.", net::canonical(server));
+            if let Some(ref mut r) = recorder {
+                r.record(Direction::Sent, server, &buf);
+                r.record(Direction::Received, server, &init_packet);
+            }
+            init = Some(init_packet);
+            Source::Live(NetThread::spawn(socket), server)
+        }
+    };
 
     //Init everything
-    let mut graphix = Renderer::init("Client Window");
-    let mut simulation = Simulation::init();
+    let mut graphix = Renderer::init("Client Window", &config.rendering);
+    let mut simulation = match config.network.mode {
+        NetMode::Snapshot => {
+            let mut simulation = Simulation::init(&config.physics);
+            print!("Done.\n");
+            run_snapshot(&config, source, init, &mut recorder, &mut graphix, &mut simulation);
+            simulation
+        }
+        NetMode::Lockstep | NetMode::Rollback => {
+            let (net_io, server) = match source {
+                Source::Live(net_io, server) => (net_io, server),
+                Source::Replay(_) => panic!("Lockstep captures only hold inputs, there are no snapshots to replay"),
+            };
+            let start = match init.as_ref().and_then(|i| lockstep::decode(i)) {
+                Some(Message::Start(start)) => start,
+                _ => panic!("Server did not start a lockstep session, is it in snapshot mode?"),
+            };
+            let mut simulation = build_world(&config, &start).unwrap_or_else(|e| panic!("{}", e));
+            print!("Done.\n");
+            run_lockstep(&config, start, net_io, server, &mut recorder, &mut graphix, &mut simulation);
+            simulation
+        }
+    };
 
-    print!("Done.\n");
+    //Do clean ups
+    graphix.clean_up();
+
+}
+
+// `init` is the packet the server answered our hello with, replays find theirs in the capture.
+// The server sends another whenever it replaces the world.
+fn run_snapshot(config: &Config, mut source: Source, mut init: Option<Vec<u8>>, recorder: &mut Option<Recorder>,
+                graphix: &mut Renderer, simulation: &mut Simulation) {
     // Do Simulation and rendering
     println!("Beginning simulation");
+    let mut joints = Vec::new();
+    let mut sparks = Sparks::new();
+    let mut picker = Picker::new();
+    let mut detector = Detector::new();
+    let mut last_tick = None;
+    let mut stepped = 1; // Steps we took since the last snapshot.
     let mut should_close = false;
 
     while !should_close {
-        let _ = socket.recv_from(&mut buf).unwrap(); // Get the state from the server
-        simulation.deserialize(&buf);
-        simulation.step();
+        // Take whatever snapshots have arrived since last frame, we keep drawing either way.
+        loop {
+            let packet = match init.take() {
+                Some(packet) => packet,
+                None => match next_packet(&mut source, recorder, &mut should_close) {
+                    Some(packet) => packet,
+                    None => break,
+                },
+            };
+            let snapshot = match packet::decode(&packet) {
+                Ok(s) => s,
+                Err(e) => {
+                    println!("Dropping bad snapshot: {}", e);
+                    continue;
+                }
+            };
+            if snapshot.init {
+                // Another one means the server loaded a save, start over from nothing.
+                if simulation.body_count() > 0 {
+                    *simulation = Simulation::init(&config.physics);
+                    graphix.forget_meshes();
+                }
+                build_scene(&snapshot, simulation);
+            }
+            // We step our own copy between snapshots, it has to step like the server's.
+            match snapshot.extension(packet::EXT_PARAMS).map(packet::decode_params) {
+                Some(Ok(params)) => if params != simulation.params() {
+                    if let Err(e) = simulation.set_params(params) {
+                        println!("Ignoring physics params: {}", e);
+                    }
+                },
+                Some(Err(e)) => println!("Ignoring bad params: {}", e),
+                None => (),
+            }
+            simulation.apply_snapshot(&snapshot);
+            joints = match snapshot.extension(packet::EXT_JOINTS).map(packet::decode_joint_lines) {
+                Some(Ok(lines)) => lines,
+                Some(Err(e)) => {
+                    println!("Ignoring bad joints: {}", e);
+                    Vec::new()
+                }
+                None => Vec::new(),
+            };
+            match snapshot.extension(packet::EXT_COLLISIONS).map(packet::decode_collisions) {
+                Some(Ok(events)) => sparks.add(&events),
+                Some(Err(e)) => println!("Ignoring bad collisions: {}", e),
+                None => (),
+            }
+            check_sync(&snapshot, &mut last_tick, stepped, &mut detector, simulation);
+            // Slow motion, fast forward and single steps, we take exactly the steps the server does.
+            stepped = match snapshot.extension(packet::EXT_STEPS).and_then(|mut s| s.read_u32::<LittleEndian>().ok()) {
+                Some(steps) => {
+                    simulation.step_by(steps);
+                    steps
+                }
+                None => simulation.step(), // Server from before step counts were sent.
+            };
+        }
+
+        draw(graphix, simulation, &joints, &mut sparks, picker.hovered);
 
-        unsafe { // Opengl calls are unsafe
-            gl::Enable(gl::DEPTH_TEST);
-            gl::DepthFunc(gl::LESS);
-            gl::ClearColor(0.38, 0.906, 0.722, 1.0);
-            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        let mut bits = 0;
+        for event in graphix.window.poll_events() {
+            bits |= handle_window_event(event, &mut should_close, &mut picker);
+        }
+        let mut local = picker.update(graphix, simulation);
+        local.bits |= bits;
+        // Nothing repeats inputs on this link like lockstep does, so rather than a single
+        // release we say we aren't holding anything every frame.
+        if !picker.holding() {
+            local.bits |= input::RELEASE;
         }
+        if let Source::Live(ref net_io, server) = source {
+            let packet = lockstep::encode_inputs(1, 0, &[(0, local)]);
+            if let Some(ref mut r) = *recorder {
+                r.record(Direction::Sent, server, &packet);
+            }
+            net_io.send(packet, server);
+        }
+
+        graphix.window.swap_buffers().unwrap();
+    }
+}
 
-        for geom_data in simulation.geoms.iter() {
-            graphix.render_cube(geom_data.0); // Seperate from ODE?
+// The next snapshot that has arrived, None when there is nothing waiting.
+fn next_packet(source: &mut Source, recorder: &mut Option<Recorder>, should_close: &mut bool) -> Option<Vec<u8>> {
+    match *source {
+        Source::Live(ref net_io, server) => {
+            while let Some((packet, from)) = net_io.poll() {
+                if !net::same_peer(from, server) {
+                    continue;
+                }
+                if let Some(ref mut r) = *recorder {
+                    r.record(Direction::Received, from, &packet);
+                }
+                return Some(packet);
+            }
+            return None;
         }
+        Source::Replay(ref mut replay) => {
+            if replay.finished {
+                println!("End of capture.");
+                *should_close = true;
+            }
+            return replay.poll();
+        }
+    }
+}
+
+// Statics and colors only come with the scene. Without one (an older server) the init packet
+// still gives us every body, they are just all grey.
+fn build_scene(snapshot: &packet::Snapshot, simulation: &mut Simulation) {
+    let text = match snapshot.extension(packet::EXT_SCENE) {
+        Some(text) => String::from_utf8_lossy(text).into_owned(),
+        None => return,
+    };
+    match Scene::parse(&text) {
+        Ok(scene) => scene.build(simulation),
+        Err(e) => println!("Ignoring the server's scene: {}", e),
+    }
+}
 
+// Bodies at rest are never sent, we simulate those ourselves and trust them to stay put. The
+// server's checksums tell us when one didn't. After a lost snapshot we are off by whatever it
+// moved, so only compare when we saw the one before and stepped as far as the server did.
+fn check_sync(snapshot: &packet::Snapshot, last_tick: &mut Option<u32>, stepped: u32, detector: &mut Detector, simulation: &Simulation) {
+    let tick = match snapshot.extension(packet::EXT_TICK).and_then(|mut t| t.read_u32::<LittleEndian>().ok()) {
+        Some(tick) => tick,
+        None => return, // Server from before ticks were sent.
+    };
+    let in_order = match *last_tick {
+        Some(last) => tick == last.wrapping_add(stepped), // The same tick again while paused.
+        None => false,
+    };
+    *last_tick = Some(tick);
+    if !in_order {
+        return;
+    }
+    if let Some(remote) = snapshot.extension(packet::EXT_CHECKSUM).and_then(Checksum::decode) {
+        let state = simulation.snapshot();
+        detector.add_local(Checksum::of(tick, &state), state);
+        detector.add_remote(remote);
+    }
+}
+
+// Physics has to match the server exactly or we drift apart on the first contact.
+fn build_world(config: &Config, start: &lockstep::Start) -> Result<Simulation, String> {
+    let mut simulation = Simulation::init(&config.physics);
+    try!(simulation.set_params(start.params).map_err(|e| format!("Server sent bad physics params: {}", e)));
+    match start.state {
+        Some(ref state) => {
+            let save = Save { scene: start.scene.clone(), world: state.clone() };
+            try!(save.build(&mut simulation).map_err(|e| format!("Server sent a bad save: {}", e)));
+        }
+        None => try!(Scene::parse(&start.scene).map_err(|e| format!("Server sent a bad scene: {}", e))).build(&mut simulation),
+    }
+    return Ok(simulation);
+}
+
+fn new_sync(start: &lockstep::Start) -> Box<InputSync> {
+    println!("Beginning {} simulation as peer {} of {}, {} ticks of input delay",
+             if start.rollback { "rollback" } else { "lockstep" }, start.peer, start.peers, start.input_delay);
+    if start.rollback {
+        Box::new(Rollback::new(start.peer, start.peers, start.session, start.input_delay))
+    } else {
+        Box::new(Lockstep::new(start.peer, start.peers, start.session, start.input_delay))
+    }
+}
+
+// We run the same scene as the server on the same inputs. Whether we wait for inputs or guess
+// them and roll back is the server's call. It sends another start when it loads a save.
+fn run_lockstep(config: &Config, mut start: lockstep::Start, net_io: NetThread, server: SocketAddr,
+                recorder: &mut Option<Recorder>, graphix: &mut Renderer, simulation: &mut Simulation) {
+    let mut sync = new_sync(&start);
+    let mut restarted = false; // Until the server's inputs for the new world arrive.
+    let mut detector = Detector::new();
+    let mut sparks = Sparks::new();
+    let mut picker = Picker::new();
+    let mut should_close = false;
+
+    while !should_close {
+        while let Some((packet, from)) = net_io.poll() {
+            if !net::same_peer(from, server) {
+                continue;
+            }
+            if let Some(ref mut r) = *recorder {
+                r.record(Direction::Received, from, &packet);
+            }
+            match lockstep::decode(&packet) {
+                Some(Message::Inputs(peer, session, inputs)) => if session == start.session {
+                    restarted = false;
+                    sync.receive(peer, &inputs);
+                },
+                // The server repeats it until our inputs for the new session reach it.
+                Some(Message::Start(next)) => if next.session != start.session {
+                    match build_world(config, &next) {
+                        Ok(world) => {
+                            *simulation = world;
+                            graphix.forget_meshes();
+                            sync = new_sync(&next);
+                            detector = Detector::new();
+                            start = next;
+                            restarted = true;
+                        }
+                        Err(e) => println!("Ignoring the server's restart: {}", e),
+                    }
+                },
+                // Checksums sent before the server restarted are for the old world.
+                None => if !restarted {
+                    if let Some(checksum) = Checksum::decode_message(&packet) {
+                        detector.add_remote(checksum);
+                    }
+                },
+            }
+        }
+
+        let mut bits = 0;
         for event in graphix.window.poll_events() {
-            handle_window_event(event, &mut should_close);
+            bits |= handle_window_event(event, &mut should_close, &mut picker);
+        }
+        let mut local = picker.update(graphix, simulation);
+        local.bits |= bits;
+        sync.add_local(local);
+        let packet = sync.schedule_local();
+        if let Some(ref mut r) = *recorder {
+            r.record(Direction::Sent, server, &packet);
+        }
+        net_io.send(packet, server);
+        sync.advance(simulation, &start.controls); // The server's, ours could differ.
+        // Our own collisions, the same ones the server would send. Only the last step's, a
+        // frame that catches up several ticks loses a few sparks.
+        let hits: Vec<_> = simulation.collision_events().iter().filter(|e| collision::replicated(e)).cloned().collect();
+        sparks.add(&hits);
+        for (tick, state) in sync.take_settled() {
+            let checksum = Checksum::of(tick, &state);
+            let message = checksum.encode_message();
+            if let Some(ref mut r) = *recorder {
+                r.record(Direction::Sent, server, &message);
+            }
+            net_io.send(message, server);
+            detector.add_local(checksum, state);
         }
 
+        let joints = simulation.joint_lines();
+        draw(graphix, simulation, &joints, &mut sparks, picker.hovered);
         graphix.window.swap_buffers().unwrap();
     }
+}
 
-    //Do clean ups
-    graphix.clean_up();
-    simulation.clean_up();
-
+// `joints` come from the server in snapshot mode, everywhere else from our own simulation.
+fn draw(graphix: &mut Renderer, simulation: &Simulation, joints: &[JointLine], sparks: &mut Sparks, hovered: Option<usize>) {
+    graphix.clear();
+    for (id, meta) in simulation.bodies() {
+        let color = if hovered == Some(id.index()) { picking::highlight(meta.color) } else { meta.color };
+        graphix.render_shape(id, &simulation.pose(id), simulation.shape(id).unwrap(), color);
+    }
+    for &(ref pose, half_extents) in simulation.static_boxes().iter() {
+        graphix.render_cube(pose, half_extents, scene::DEFAULT_COLOR);
+    }
+    graphix.render_lines(&simulation.joint_segments(joints), scene::JOINT_COLOR);
+    graphix.render_lines(&sparks.segments(), scene::SPARK_COLOR);
 }
 
-fn handle_window_event(event: glutin::Event, should_close: &mut bool) {
+// Returns the input bits for whatever the event asks the player to do. The mouse goes to the picker.
+fn handle_window_event(event: glutin::Event, should_close: &mut bool, picker: &mut Picker) -> u8 {
     use glutin::Event;
     use glutin::ElementState as KeyState;
     use glutin::VirtualKeyCode as Key;
@@ -70,7 +386,23 @@ fn handle_window_event(event: glutin::Event, should_close: &mut bool) {
     match event {
         Event::KeyboardInput(KeyState::Pressed, _, Some(Key::Q)) => {
             *should_close = true;
+            0
+        }
+        Event::MouseMoved(x, y) => {
+            picker.moved(x, y);
+            0
+        }
+        Event::MouseInput(state, glutin::MouseButton::Left) => {
+            picker.button(state == KeyState::Pressed);
+            0
         }
-        _ => ()
+        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::P)) => input::PAUSE,
+        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::Period)) => input::STEP,
+        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::Space)) => input::LEVITATE,
+        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::Up)) => input::UP,
+        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::Down)) => input::DOWN,
+        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::Left)) => input::LEFT,
+        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::Right)) => input::RIGHT,
+        _ => 0
     }
 }
@@ -0,0 +1,79 @@
+#![allow(dead_code)]
+
+use std;
+
+// Fastest we let the world run, every step of a tick happens inside one frame.
+pub const MAX_TIME_SCALE: f32 = 8.0;
+pub const MIN_TIME_SCALE: f32 = 1.0 / 64.0;
+
+// How many physics steps each tick takes. A tick is one call to Simulation::step, once a frame
+// on the server. It is part of the world state so every lockstep peer and every rollback
+// replay steps exactly as often as the others.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Clock {
+    scale: f32, // Steps per tick, below 1 for slow motion.
+    owed: f32, // The part of a step slow motion has saved up.
+    queued: u32, // Single steps asked for while paused, one is taken per tick.
+    run_for: Option<u32>, // Steps left before pausing again.
+}
+
+impl Default for Clock {
+    fn default() -> Clock {
+        Clock { scale: 1.0, owed: 0.0, queued: 0, run_for: None }
+    }
+}
+
+pub fn check_scale(scale: f32) -> Result<(), String> {
+    if !(scale >= MIN_TIME_SCALE && scale <= MAX_TIME_SCALE) {
+        return Err(format!("time scale {} is not between {} and {}", scale, MIN_TIME_SCALE, MAX_TIME_SCALE));
+    }
+    return Ok(());
+}
+
+impl Clock {
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    pub fn set_scale(&mut self, scale: f32) -> Result<(), String> {
+        try!(check_scale(scale));
+        self.scale = scale;
+        return Ok(());
+    }
+
+    // Does nothing unless paused when the tick comes.
+    pub fn single_step(&mut self) {
+        self.queued += 1;
+    }
+
+    pub fn run(&mut self, steps: u32) {
+        self.run_for = if steps > 0 { Some(steps) } else { None };
+    }
+
+    pub fn running_for(&self) -> Option<u32> {
+        self.run_for
+    }
+
+    // The steps to take this tick. Pauses once a run is over.
+    pub fn advance(&mut self, paused: &mut bool) -> u32 {
+        if *paused {
+            self.owed = 0.0;
+            let steps = std::cmp::min(self.queued, 1);
+            self.queued -= steps;
+            return steps;
+        }
+        self.queued = 0; // Left over from before we unpaused.
+        self.owed += self.scale;
+        let whole = self.owed.floor();
+        self.owed -= whole;
+        let mut steps = whole as u32;
+        if let Some(left) = self.run_for {
+            steps = std::cmp::min(steps, left);
+            self.run_for = if left > steps { Some(left - steps) } else { None };
+            if self.run_for.is_none() {
+                *paused = true;
+            }
+        }
+        return steps;
+    }
+}
@@ -0,0 +1,112 @@
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Phase {
+    Begin,   // First step the two touch.
+    Persist, // Still touching.
+    End,     // Stopped touching this step, point and normal are from the last contact.
+}
+
+// Two geoms touching during the last step. body1 is always a body, body2 is None for statics
+// and the ground. The normal points from body2 into body1.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CollisionEvent {
+    pub phase: Phase,
+    pub body1: usize,
+    pub body2: Option<usize>,
+    pub point: [f32; 3], // Middle of the contact points.
+    pub normal: [f32; 3],
+    pub impulse: f32, // Along the normal over the whole step, in newton seconds. 0 for End.
+}
+
+// Begins at least this hard are worth telling clients about, softer ones are things settling.
+pub const REPLICATE_IMPULSE: f32 = 1.0;
+
+// Which events snapshot clients get, they can't see anything the server doesn't send.
+pub fn replicated(event: &CollisionEvent) -> bool {
+    event.phase == Phase::Begin && event.impulse >= REPLICATE_IMPULSE
+}
+
+const SPARK_FRAMES: u32 = 20;
+
+// Client side effect for hits: a short line out of the contact point, longer for harder hits,
+// that shrinks away over SPARK_FRAMES frames.
+pub struct Sparks {
+    live: Vec<(CollisionEvent, u32)>,
+}
+
+impl Sparks {
+    pub fn new() -> Sparks {
+        Sparks { live: Vec::new() }
+    }
+
+    pub fn add(&mut self, events: &[CollisionEvent]) {
+        for e in events.iter() {
+            self.live.push((*e, SPARK_FRAMES));
+        }
+    }
+
+    // Call once a frame.
+    pub fn segments(&mut self) -> Vec<([f32; 3], [f32; 3])> {
+        self.live.retain(|&(_, frames)| frames > 0);
+        let mut segments = Vec::new();
+        for &mut (ref e, ref mut frames) in self.live.iter_mut() {
+            let length = e.impulse.min(10.0) * 0.2 * *frames as f32 / SPARK_FRAMES as f32;
+            let p = e.point;
+            segments.push((p, [p[0] + e.normal[0] * length, p[1] + e.normal[1] * length, p[2] + e.normal[2] * length]));
+            *frames -= 1;
+        }
+        return segments;
+    }
+}
+
+// The two geoms as usize so pairs can be map keys, smaller one first.
+pub type PairKey = (usize, usize);
+
+// What the simulation remembers about a touching pair between steps.
+#[derive(Clone, Copy)]
+pub struct Touch {
+    pub body1: usize,
+    pub body2: Option<usize>,
+    pub point: [f32; 3],
+    pub normal: [f32; 3],
+}
+
+// Turns the pairs touching this step (with their impulses) into events, given the ones
+// touching last step, and updates `last` for next time. ODE doesn't collide sleeping bodies so
+// their pairs never show up, `asleep` says which of those are still touching, just quietly.
+pub fn diff<F>(last: &mut BTreeMap<PairKey, Touch>, now: BTreeMap<PairKey, (Touch, f32)>, asleep: F) -> Vec<CollisionEvent>
+    where F: Fn(&Touch) -> bool {
+    let mut events = Vec::new();
+    let mut touching = BTreeMap::new();
+    for (key, (touch, impulse)) in now.into_iter() {
+        let phase = if last.contains_key(&key) { Phase::Persist } else { Phase::Begin };
+        events.push(event(phase, &touch, impulse));
+        touching.insert(key, touch);
+    }
+    for (key, touch) in last.iter() {
+        if touching.contains_key(key) {
+            continue;
+        }
+        if asleep(touch) {
+            touching.insert(*key, *touch);
+        } else {
+            events.push(event(Phase::End, touch, 0.0));
+        }
+    }
+    *last = touching;
+    return events;
+}
+
+fn event(phase: Phase, touch: &Touch, impulse: f32) -> CollisionEvent {
+    CollisionEvent {
+        phase: phase,
+        body1: touch.body1,
+        body2: touch.body2,
+        point: touch.point,
+        normal: touch.normal,
+        impulse: impulse,
+    }
+}
@@ -0,0 +1,251 @@
+#![allow(dead_code)]
+
+extern crate toml;
+
+use std;
+use std::fs::File;
+use std::path::Path;
+use std::io::prelude::*;
+use params::{PhysicsParams, MAX_CONTACTS};
+
+// Every field has a default so a config file only needs the values it changes.
+// See config.toml in the repository root for the documented defaults.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Config {
+    pub network: NetworkConfig,
+    pub physics: PhysicsConfig,
+    pub controls: ControlsConfig,
+    pub rendering: RenderingConfig,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum NetMode {
+    Snapshot, // The server simulates and streams poses.
+    Lockstep, // Everyone simulates, only inputs are sent.
+    Rollback, // Like lockstep but guesses late inputs and rewinds when it guessed wrong.
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct NetworkConfig {
+    pub mode: NetMode,
+    pub input_delay: u32, // Lockstep only, in ticks.
+    pub server_host: String,
+    pub server_port: u16,
+    pub client_port: u16,
+    pub record: Option<String>, // Capture file for every datagram sent and received.
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct PhysicsConfig {
+    pub gravity: f32,
+    pub cfm: f32,
+    pub step_size: f32,
+    pub rest_threshold: f32, // Cubes slower than this on every axis are not sent.
+    pub max_contacts: usize, // Per colliding pair, 1 to params::MAX_CONTACTS.
+    pub erp: f32,
+    pub iterations: u32,
+    pub contact_surface_layer: f32,
+    pub max_correcting_vel: f32,
+    pub auto_disable: bool,
+    pub auto_disable_linear: f32,
+    pub auto_disable_angular: f32,
+    pub auto_disable_steps: u32,
+    pub auto_disable_time: f32,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ControlsConfig {
+    pub push_force: f32,
+    pub levitation_force: f32,
+    pub drag_stiffness: f32, // Mouse spring, per kg so heavy and light bodies drag alike.
+    pub drag_damping: f32,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RenderingConfig {
+    pub clear_color: [f32; 3],
+    pub fov: f32, // Degrees
+    pub camera_eye: [f32; 3],
+    pub camera_target: [f32; 3],
+    pub camera_up: [f32; 3],
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            network: Default::default(),
+            physics: Default::default(),
+            controls: Default::default(),
+            rendering: Default::default(),
+        }
+    }
+}
+
+impl Default for NetworkConfig {
+    fn default() -> NetworkConfig {
+        NetworkConfig {
+            mode: NetMode::Snapshot,
+            input_delay: 3,
+            server_host: "localhost".to_string(),
+            server_port: 35555,
+            client_port: 35556,
+            record: None,
+        }
+    }
+}
+
+impl Default for PhysicsConfig {
+    fn default() -> PhysicsConfig {
+        PhysicsConfig {
+            gravity: -10.0,
+            cfm: 0.0001,
+            step_size: 0.01,
+            rest_threshold: 0.1,
+            max_contacts: 4,
+            // ODE's own defaults.
+            erp: 0.2,
+            iterations: 20,
+            contact_surface_layer: 0.0,
+            max_correcting_vel: std::f32::INFINITY,
+            auto_disable: false,
+            auto_disable_linear: 0.01,
+            auto_disable_angular: 0.01,
+            auto_disable_steps: 10,
+            auto_disable_time: 0.0,
+        }
+    }
+}
+
+impl Default for ControlsConfig {
+    fn default() -> ControlsConfig {
+        ControlsConfig {
+            push_force: 500.0,
+            levitation_force: 250.0,
+            drag_stiffness: 200.0,
+            drag_damping: 20.0,
+        }
+    }
+}
+
+impl Default for RenderingConfig {
+    fn default() -> RenderingConfig {
+        RenderingConfig {
+            clear_color: [0.38, 0.906, 0.722],
+            fov: 95.0,
+            camera_eye: [10.0, 2.0, -10.0],
+            camera_target: [10.0, 0.0, 10.0],
+            camera_up: [0.0, 1.0, 0.0],
+        }
+    }
+}
+
+impl PhysicsConfig {
+    // What a world starts with, max_contacts is clamped into range.
+    pub fn params(&self) -> PhysicsParams {
+        PhysicsParams {
+            gravity: self.gravity,
+            cfm: self.cfm,
+            erp: self.erp,
+            iterations: self.iterations,
+            step_size: self.step_size,
+            contact_surface_layer: self.contact_surface_layer,
+            max_correcting_vel: self.max_correcting_vel,
+            max_contacts: std::cmp::max(1, std::cmp::min(self.max_contacts, MAX_CONTACTS)),
+            auto_disable: self.auto_disable,
+            auto_disable_linear: self.auto_disable_linear,
+            auto_disable_angular: self.auto_disable_angular,
+            auto_disable_steps: self.auto_disable_steps,
+            auto_disable_time: self.auto_disable_time,
+        }
+    }
+}
+
+impl ControlsConfig {
+    // The drag spring's ERP and CFM divide by these, lockstep clients get them off the wire.
+    pub fn check(&self) -> Result<(), String> {
+        if !self.push_force.is_finite() || !self.levitation_force.is_finite() {
+            return Err(format!("controls forces {} and {} are not numbers", self.push_force, self.levitation_force));
+        }
+        if !(self.drag_stiffness > 0.0) || !self.drag_stiffness.is_finite() {
+            return Err(format!("controls.drag_stiffness {} is not positive", self.drag_stiffness));
+        }
+        if !(self.drag_damping >= 0.0) || !self.drag_damping.is_finite() {
+            return Err(format!("controls.drag_damping {} is negative", self.drag_damping));
+        }
+        return Ok(());
+    }
+}
+
+impl Config {
+    // Reads `--config <file>` (or ./config.toml when present) and then applies any
+    // `section.key=value` overrides in argument order. Returns the config and the
+    // arguments it did not consume.
+    pub fn from_args<I: Iterator<Item=String>>(args: I) -> (Config, Vec<String>) {
+        let mut path = None;
+        let mut overrides = Vec::new();
+        let mut rest = Vec::new();
+        let mut args = args.peekable();
+        while let Some(arg) = args.next() {
+            if arg == "--config" {
+                path = Some(args.next().expect("--config needs a file"));
+            } else if !arg.starts_with("-") && arg.contains('=') && arg.split('=').next().unwrap().contains('.') {
+                overrides.push(arg);
+            } else {
+                rest.push(arg);
+            }
+        }
+
+        let mut value = match path {
+            Some(ref p) => Config::read_file(Path::new(p)),
+            None if Path::new("config.toml").exists() => Config::read_file(Path::new("config.toml")),
+            None => toml::Value::Table(toml::value::Table::new()),
+        };
+        for o in overrides.iter() {
+            apply_override(&mut value, o);
+        }
+
+        let config = value.try_into::<Config>().unwrap_or_else(|e| panic!("Invalid configuration: {}", e));
+        config.controls.check().unwrap_or_else(|e| panic!("Invalid configuration: {}", e));
+        return (config, rest);
+    }
+
+    fn read_file(path: &Path) -> toml::Value {
+        let mut file = File::open(path).unwrap_or_else(|e| panic!("Could not open {}: {}", path.display(), e));
+        let mut source = String::new();
+        file.read_to_string(&mut source).unwrap();
+        println!("Read in config file {}", path.display());
+        return source.parse::<toml::Value>().unwrap_or_else(|e| panic!("Could not parse {}: {}", path.display(), e));
+    }
+}
+
+// "physics.gravity=-9.8" sets [physics] gravity. Values are parsed as TOML and
+// anything that doesn't parse (a bare hostname say) is taken as a string.
+fn apply_override(root: &mut toml::Value, arg: &str) {
+    let mut split = arg.splitn(2, '=');
+    let key = split.next().unwrap();
+    let raw = split.next().unwrap();
+    let value = match format!("v = {}", raw).parse::<toml::Value>() {
+        Ok(toml::Value::Table(mut t)) => t.remove("v").unwrap(),
+        _ => toml::Value::String(raw.to_string()),
+    };
+
+    let path: Vec<&str> = key.split('.').collect();
+    let mut table = root;
+    for section in path[..path.len()-1].iter() {
+        let t = match *table {
+            toml::Value::Table(ref mut t) => t,
+            _ => panic!("Override {} does not name a section", arg),
+        };
+        table = t.entry(section.to_string()).or_insert(toml::Value::Table(toml::value::Table::new()));
+    }
+    match *table {
+        toml::Value::Table(ref mut t) => { t.insert(path[path.len()-1].to_string(), value); }
+        _ => panic!("Override {} does not name a section", arg),
+    }
+}
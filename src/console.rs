@@ -0,0 +1,95 @@
+#![allow(dead_code)]
+
+use std;
+use std::io::prelude::*;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use save;
+use params;
+use clock;
+
+pub enum Command {
+    Save(String),
+    Load(String),
+    Set(String, String), // Physics param name and value.
+    Params,
+    Step, // Once, while paused.
+    Run(u32), // Steps, then pause.
+    Speed(f32), // Time scale.
+}
+
+const HELP: &'static str = "Commands:
+  save [file]  write the world to file, quicksave.json if not given
+  load [file]  replace the world with a save
+  set <param> <value>  change a physics param, clients follow
+  params       list the physics params and their values
+  step         take one step while paused
+  run <steps>  run that many steps and pause, 0 stops a run
+  speed <scale>  slow motion below 1, fast forward above";
+
+// Commands typed into the terminal the server was started from. Reading stdin blocks, so it
+// gets a thread like the sockets do.
+pub struct Console {
+    lines: Receiver<String>,
+}
+
+impl Console {
+    pub fn spawn() -> Console {
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            let stdin = std::io::stdin();
+            for line in stdin.lock().lines() {
+                match line {
+                    Ok(line) => if tx.send(line).is_err() { return; },
+                    Err(_) => return, // stdin closed, nobody is typing anything.
+                }
+            }
+        });
+        return Console { lines: rx };
+    }
+
+    // The next command typed, never blocks. Anything that isn't a command gets the help text.
+    pub fn poll(&self) -> Option<Command> {
+        while let Ok(line) = self.lines.try_recv() {
+            match parse(&line) {
+                Ok(Some(command)) => return Some(command),
+                Ok(None) => (),
+                Err(e) => println!("{}\n{}", e, HELP),
+            }
+        }
+        return None;
+    }
+}
+
+pub fn parse(line: &str) -> Result<Option<Command>, String> {
+    let mut words = line.split_whitespace();
+    let command = match words.next() {
+        Some(c) => c,
+        None => return Ok(None),
+    };
+    let arg = words.next();
+    let file = arg.unwrap_or(save::QUICKSAVE).to_string();
+    return match command {
+        "save" => Ok(Some(Command::Save(file))),
+        "load" => Ok(Some(Command::Load(file))),
+        "set" => match (arg, words.next()) {
+            (Some(name), Some(value)) => Ok(Some(Command::Set(name.to_string(), value.to_string()))),
+            _ => Err(format!("set needs a param and a value, one of {}", params::NAMES.join(", "))),
+        },
+        "params" => Ok(Some(Command::Params)),
+        "step" => Ok(Some(Command::Step)),
+        "run" => match arg.map(|a| a.parse()) {
+            Some(Ok(steps)) => Ok(Some(Command::Run(steps))),
+            _ => Err("run needs a number of steps".to_string()),
+        },
+        "speed" => match arg.map(|a| a.parse()) {
+            Some(Ok(scale)) => clock::check_scale(scale).map(|_| Some(Command::Speed(scale))),
+            _ => Err("speed needs a time scale".to_string()),
+        },
+        "help" => {
+            println!("{}", HELP);
+            Ok(None)
+        }
+        _ => Err(format!("Unknown command {}", command)),
+    };
+}
@@ -0,0 +1,173 @@
+#![allow(dead_code)]
+
+extern crate byteorder;
+
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Cursor;
+use byteorder::{LittleEndian, WriteBytesExt, ReadBytesExt};
+use simulation::{BodyState, WorldState};
+use packet;
+
+// How often, in ticks, peers compare checksums.
+pub const INTERVAL: u32 = 60;
+
+// Lockstep message tag, next to lockstep's start (0) and inputs (1).
+const CHECKSUM: u8 = 2;
+
+// Poses are rounded before hashing so the last few bits of float noise don't count, only
+// differences someone could see. Velocities are left out, snapshot clients never get them.
+const POS_QUANTUM: f32 = 1024.0; // 1mm
+const ROT_QUANTUM: f32 = 16384.0;
+
+fn fnv(hash: u64, bytes: &[u8]) -> u64 {
+    let mut h = hash;
+    for b in bytes.iter() {
+        h ^= *b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    return h;
+}
+
+fn fnv_i32(hash: u64, v: i32) -> u64 {
+    fnv(hash, &[v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8])
+}
+
+pub fn body_hash(body: &BodyState) -> u32 {
+    let mut h = 0xcbf29ce484222325;
+    for p in body.pos.iter() {
+        h = fnv_i32(h, (p * POS_QUANTUM).round() as i32);
+    }
+    // q and -q are the same orientation, only hash one of them.
+    let sign = if body.quat[0] < 0.0 { -1.0 } else { 1.0 };
+    for q in body.quat.iter() {
+        h = fnv_i32(h, (q * sign * ROT_QUANTUM).round() as i32);
+    }
+    h = fnv(h, &[body.enabled as u8]);
+    return (h ^ (h >> 32)) as u32;
+}
+
+// Hash of the whole world at a tick plus one hash per body, so whoever spots a mismatch can
+// also tell which body went first.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Checksum {
+    pub tick: u32,
+    pub hash: u64,
+    pub bodies: Vec<u32>,
+}
+
+impl Checksum {
+    pub fn of(tick: u32, state: &WorldState) -> Checksum {
+        let bodies: Vec<u32> = state.bodies.iter().map(body_hash).collect();
+        let mut hash = 0xcbf29ce484222325;
+        for b in bodies.iter() {
+            hash = fnv_i32(hash, *b as i32);
+        }
+        return Checksum { tick: tick, hash: hash, bodies: bodies };
+    }
+
+    // u32 tick, u64 hash, u32 body count, body count * u32
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        buf.write_u32::<LittleEndian>(self.tick).unwrap();
+        buf.write_u64::<LittleEndian>(self.hash).unwrap();
+        buf.write_u32::<LittleEndian>(self.bodies.len() as u32).unwrap();
+        for b in self.bodies.iter() {
+            buf.write_u32::<LittleEndian>(*b).unwrap();
+        }
+        return buf;
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Checksum> {
+        Checksum::read(&mut Cursor::new(buf)).ok()
+    }
+
+    fn read(input: &mut Cursor<&[u8]>) -> io::Result<Checksum> {
+        let tick = try!(input.read_u32::<LittleEndian>());
+        let hash = try!(input.read_u64::<LittleEndian>());
+        let count = try!(input.read_u32::<LittleEndian>()) as usize;
+        if count * 4 > input.get_ref().len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "body count too large"));
+        }
+        let mut bodies = Vec::with_capacity(count);
+        for _ in 0..count {
+            bodies.push(try!(input.read_u32::<LittleEndian>()));
+        }
+        return Ok(Checksum { tick: tick, hash: hash, bodies: bodies });
+    }
+
+    // As a lockstep message.
+    pub fn encode_message(&self) -> Vec<u8> {
+        let mut buf = vec![CHECKSUM];
+        buf.extend_from_slice(&self.encode());
+        return buf;
+    }
+
+    pub fn decode_message(buf: &[u8]) -> Option<Checksum> {
+        if buf.first() == Some(&CHECKSUM) { Checksum::decode(&buf[1..]) } else { None }
+    }
+}
+
+// Matches up our checksums with the other side's as they arrive, in whichever order.
+pub struct Detector {
+    local: BTreeMap<u32, (Checksum, WorldState)>,
+    remote: BTreeMap<u32, Checksum>,
+    pub desyncs: u32,
+}
+
+impl Detector {
+    pub fn new() -> Detector {
+        Detector { local: BTreeMap::new(), remote: BTreeMap::new(), desyncs: 0 }
+    }
+
+    pub fn add_local(&mut self, checksum: Checksum, state: WorldState) {
+        let tick = checksum.tick;
+        self.local.insert(tick, (checksum, state));
+        self.compare(tick);
+    }
+
+    pub fn add_remote(&mut self, checksum: Checksum) {
+        let tick = checksum.tick;
+        self.remote.insert(tick, checksum);
+        self.compare(tick);
+    }
+
+    fn compare(&mut self, tick: u32) {
+        let matched = match (self.local.get(&tick), self.remote.get(&tick)) {
+            (Some(&(ref local, ref state)), Some(remote)) => {
+                if local.hash != remote.hash {
+                    report(local, remote, state);
+                    false
+                } else {
+                    true
+                }
+            }
+            _ => return, // Still waiting on one side.
+        };
+        if !matched {
+            self.desyncs += 1;
+        }
+        // Nothing older can still find its partner once this tick is settled.
+        let keep_local = self.local.split_off(&(tick + 1));
+        self.local = keep_local;
+        let keep_remote = self.remote.split_off(&(tick + 1));
+        self.remote = keep_remote;
+    }
+}
+
+fn report(local: &Checksum, remote: &Checksum, state: &WorldState) {
+    println!("DESYNC at tick {}: local hash {:016x}, remote {:016x}", local.tick, local.hash, remote.hash);
+    if local.bodies.len() != remote.bodies.len() {
+        println!("  local world has {} bodies, remote has {}", local.bodies.len(), remote.bodies.len());
+    }
+    match local.bodies.iter().zip(remote.bodies.iter()).position(|(l, r)| l != r) {
+        Some(i) => {
+            let b = &state.bodies[i];
+            println!("  first differing body is #{} (local {:08x}, remote {:08x}), ours is", i, local.bodies[i], remote.bodies[i]);
+            println!("    pos={} quat=({},{},{},{}) enabled={}", packet::fmt_3(b.pos),
+                     b.quat[0], b.quat[1], b.quat[2], b.quat[3], b.enabled);
+            println!("    lin_vel={} ang_vel={}", packet::fmt_3(b.lin_vel), packet::fmt_3(b.ang_vel));
+        }
+        None => println!("  every shared body matches, the difference is in the body count"),
+    }
+}
@@ -0,0 +1,178 @@
+#![allow(dead_code)]
+
+use std;
+
+use shape::Shape;
+
+// A handle into an Entities store: the slot plus which occupant of the slot it was made for.
+// Removing something bumps its slot's generation, so handles to it stop matching instead of
+// quietly pointing at whatever moves in next.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct EntityId {
+    index: u32,
+    generation: u32,
+}
+
+impl EntityId {
+    // The slot, what collision events, ray hits and the wire formats call a body.
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+}
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+// Things by EntityId. Freed slots are reused, newest freed first.
+pub struct Entities<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+}
+
+impl<T> Entities<T> {
+    pub fn new() -> Entities<T> {
+        Entities { slots: Vec::new(), free: Vec::new() }
+    }
+
+    pub fn insert(&mut self, value: T) -> EntityId {
+        if let Some(index) = self.free.pop() {
+            let slot = &mut self.slots[index as usize];
+            slot.value = Some(value);
+            return EntityId { index: index, generation: slot.generation };
+        }
+        self.slots.push(Slot { generation: 0, value: Some(value) });
+        return EntityId { index: (self.slots.len() - 1) as u32, generation: 0 };
+    }
+
+    pub fn remove(&mut self, id: EntityId) -> Option<T> {
+        if !self.contains(id) {
+            return None;
+        }
+        let slot = &mut self.slots[id.index()];
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(id.index);
+        return slot.value.take();
+    }
+
+    pub fn contains(&self, id: EntityId) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub fn get(&self, id: EntityId) -> Option<&T> {
+        match self.slots.get(id.index()) {
+            Some(slot) if slot.generation == id.generation => slot.value.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, id: EntityId) -> Option<&mut T> {
+        match self.slots.get_mut(id.index()) {
+            Some(slot) if slot.generation == id.generation => slot.value.as_mut(),
+            _ => None,
+        }
+    }
+
+    // The current handle for whatever is in the slot, for turning indices off the wire back
+    // into handles.
+    pub fn id_at(&self, index: usize) -> Option<EntityId> {
+        match self.slots.get(index) {
+            Some(slot) if slot.value.is_some() => Some(EntityId { index: index as u32, generation: slot.generation }),
+            _ => None,
+        }
+    }
+
+    // How many are alive.
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // One past the highest slot ever used, indices below it may be empty.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    // In slot order.
+    pub fn iter(&self) -> Iter<T> {
+        Iter { slots: self.slots.iter().enumerate() }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<T> {
+        IterMut { slots: self.slots.iter_mut().enumerate() }
+    }
+}
+
+pub struct Iter<'a, T: 'a> {
+    slots: std::iter::Enumerate<std::slice::Iter<'a, Slot<T>>>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (EntityId, &'a T);
+
+    fn next(&mut self) -> Option<(EntityId, &'a T)> {
+        while let Some((i, slot)) = self.slots.next() {
+            if let Some(ref value) = slot.value {
+                return Some((EntityId { index: i as u32, generation: slot.generation }, value));
+            }
+        }
+        return None;
+    }
+}
+
+pub struct IterMut<'a, T: 'a> {
+    slots: std::iter::Enumerate<std::slice::IterMut<'a, Slot<T>>>,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = (EntityId, &'a mut T);
+
+    fn next(&mut self) -> Option<(EntityId, &'a mut T)> {
+        while let Some((i, slot)) = self.slots.next() {
+            let generation = slot.generation;
+            if let Some(ref mut value) = slot.value {
+                return Some((EntityId { index: i as u32, generation: generation }, value));
+            }
+        }
+        return None;
+    }
+}
+
+pub const DEFAULT_COLOR: [f32; 3] = [0.5, 0.5, 0.5];
+
+// Flags on EntityMeta.
+pub const PLAYER: u32 = 1; // The cube the keys push around.
+
+// Everything about a body that isn't physics, for drawing, the console and the wire. Most of
+// the wire calls a body by its EntityId index, which matches on every peer because they all
+// build the world in the same order. Grabs use net_id instead, they're decided ticks before
+// they're applied and net ids are never reused, so a late one can't land on a new body in an
+// old slot. The shape and net_id are set once when the body is made: ODE's geom was built from
+// the shape and by_net_id relies on net_id, so they only have getters.
+#[derive(Clone)]
+pub struct EntityMeta {
+    pub name: Option<String>, // From the scene, for the console and debugging.
+    pub color: [f32; 3],
+    pub owner: Option<usize>, // The peer dragging it, None while nobody is. Set by Simulation::drag.
+    pub flags: u32,
+    shape: Shape,
+    net_id: u32,
+}
+
+impl EntityMeta {
+    pub fn new(shape: Shape, net_id: u32) -> EntityMeta {
+        EntityMeta { name: None, color: DEFAULT_COLOR, owner: None, flags: 0, shape: shape, net_id: net_id }
+    }
+
+    pub fn shape(&self) -> &Shape {
+        &self.shape
+    }
+
+    pub fn net_id(&self) -> u32 {
+        self.net_id
+    }
+}
@@ -0,0 +1,114 @@
+#![allow(dead_code)]
+
+use vec::Vec3;
+use simulation::Simulation;
+use config::ControlsConfig;
+use params::PhysicsParams;
+
+// Everything a player can do in one tick, packed so it is cheap to send every tick.
+pub const UP: u8 = 1;
+pub const DOWN: u8 = 2;
+pub const LEFT: u8 = 4;
+pub const RIGHT: u8 = 8;
+pub const LEVITATE: u8 = 16;
+pub const PAUSE: u8 = 32;
+pub const RELEASE: u8 = 64; // Let go of whatever this peer is dragging, it keeps its speed.
+pub const STEP: u8 = 128; // One step while paused.
+
+// Pull a body towards a point with the mouse spring, starting the drag if it isn't held yet.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Grab {
+    pub body: usize, // Its net id, see EntityMeta.
+    pub target: [f32; 3],
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Input {
+    pub bits: u8,
+    pub grab: Option<Grab>,
+    pub params: Option<PhysicsParams>, // New physics params from the server, ignored from anyone else.
+    pub time_scale: Option<f32>, // Also only from the server, like run.
+    pub run: Option<u32>, // Steps to run before pausing, 0 stops a run.
+}
+
+impl Input {
+    pub fn bits(bits: u8) -> Input {
+        Input { bits: bits, ..Default::default() }
+    }
+
+    // Folds in input gathered later in the same tick, the latest grab, params and time controls win.
+    pub fn merge(&mut self, other: Input) {
+        self.bits |= other.bits;
+        if other.grab.is_some() {
+            self.grab = other.grab;
+        }
+        if other.params.is_some() {
+            self.params = other.params;
+        }
+        if other.time_scale.is_some() {
+            self.time_scale = other.time_scale;
+        }
+        if other.run.is_some() {
+            self.run = other.run;
+        }
+    }
+}
+
+// Acts on the player cube, body simulation.player, and on whatever `peer` drags around.
+// Must be a pure function of its inputs so every lockstep peer ends up in the same place.
+pub fn apply(simulation: &mut Simulation, peer: usize, input: Input, controls: &ControlsConfig) {
+    if peer == 0 {
+        if let Some(params) = input.params {
+            if let Err(e) = simulation.set_params(params) {
+                println!("Ignoring physics params: {}", e);
+            }
+        }
+        if let Some(scale) = input.time_scale {
+            if let Err(e) = simulation.set_time_scale(scale) {
+                println!("Ignoring {}", e);
+            }
+        }
+        if let Some(steps) = input.run {
+            simulation.run_for(steps);
+        }
+    }
+    let bits = input.bits;
+    if bits & PAUSE != 0 {
+        simulation.toggle_pause();
+    }
+    if bits & STEP != 0 {
+        simulation.single_step();
+    }
+    if bits & RELEASE != 0 {
+        simulation.release(peer);
+    }
+    if let Some(grab) = input.grab {
+        let body = simulation.by_net_id(grab.body as u32);
+        if body.is_some() && grab.target.iter().all(|t| t.is_finite()) {
+            let t = grab.target;
+            simulation.drag(peer, body.unwrap(), Vec3::new(t[0], t[1], t[2]), controls.drag_stiffness, controls.drag_damping);
+        }
+    }
+    let player = match simulation.body_at(simulation.player) {
+        Some(player) => player,
+        None => return,
+    };
+    let push_force = controls.push_force;
+    if bits & LEVITATE != 0 {
+        let lev_force = controls.levitation_force;
+        let pos = simulation.position(player);
+        simulation.apply_force(player, Vec3::new(0.0, (lev_force-(pos.z * 5.0).powf(3.0)).min(0.0), 0.0));
+    }
+    if bits & UP != 0 {
+        simulation.apply_force(player, Vec3::new(0.0, 0.0, push_force));
+    }
+    if bits & DOWN != 0 {
+        simulation.apply_force(player, Vec3::new(0.0, 0.0, -push_force));
+    }
+    if bits & LEFT != 0 {
+        simulation.apply_force(player, Vec3::new(push_force, 0.0, 0.0));
+    }
+    if bits & RIGHT != 0 {
+        simulation.apply_force(player, Vec3::new(-push_force, 0.0, 0.0));
+    }
+}
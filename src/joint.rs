@@ -0,0 +1,23 @@
+#![allow(dead_code)]
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum JointKind {
+    Ball,   // Turns every way around the anchor.
+    Hinge,  // Turns around the axis through the anchor.
+    Slider, // Slides along the axis, no turning.
+    Fixed,  // Holds the bodies where they were when it was made.
+}
+
+// Index into Simulation's joints. Stays the same while the joint lives, broken ones included,
+// once it is destroyed the next joint made can get the same one.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct JointId(pub usize);
+
+// Enough to draw a joint: a line from each body to where they are joined.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct JointLine {
+    pub body1: usize,
+    pub body2: Option<usize>, // None when joined to the world.
+    pub anchor: [f32; 3],
+}
@@ -0,0 +1,33 @@
+// The headless parts of the project. The binaries use these from here and only keep the
+// window code (and the server its console) to themselves, the fuzz targets and tests/ use
+// them too.
+extern crate libc;
+extern crate ode;
+extern crate byteorder;
+extern crate toml;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+
+pub mod vec;
+pub mod config;
+pub mod params;
+pub mod clock;
+pub mod packet;
+pub mod shape;
+pub mod joint;
+pub mod material;
+pub mod collision;
+pub mod query;
+pub mod capture;
+pub mod net;
+pub mod ode_handle;
+pub mod entity;
+pub mod simulation;
+pub mod input;
+pub mod scene;
+pub mod save;
+pub mod lockstep;
+pub mod rollback;
+pub mod desync;
+pub mod fuzz;
@@ -0,0 +1,350 @@
+#![allow(dead_code)]
+
+extern crate byteorder;
+extern crate serde_json;
+
+use std;
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Cursor;
+use std::io::prelude::*;
+use std::time::Instant;
+use byteorder::{LittleEndian, WriteBytesExt, ReadBytesExt};
+use config::ControlsConfig;
+use params::PhysicsParams;
+use packet;
+use simulation::{Simulation, WorldState};
+use desync;
+use input::{self, Input, Grab};
+
+// Lockstep messages, all little endian:
+//   start:  u8 0, u8 mode (0 lockstep, 1 rollback), u8 peer index, u8 peer count, u8 session,
+//           u32 input delay, physics params (packet::encode_params), f32 push force,
+//           levitation force, drag stiffness, drag damping, u32 length, scene file text,
+//           u32 length, WorldState as JSON when starting from a save (0 length otherwise)
+//   inputs: u8 1, u8 peer index, u8 session, u8 count, count * (u32 tick, u8 input bits, u8 extras,
+//           when extras has EXTRA_GRAB: u32 body, 3 f32 target,
+//           when extras has EXTRA_PARAMS: physics params, EXTRA_TIME_SCALE: f32 scale,
+//           EXTRA_RUN: u32 steps)
+// Input messages repeat our most recent inputs so a lost datagram is covered by the next one.
+// The server sends a new start when it replaces the world, with the session one higher, and
+// ticks count from 0 again. Inputs still on their way from the old session carry its number.
+const START: u8 = 0;
+const INPUTS: u8 = 1;
+pub const REDUNDANCY: usize = 16;
+const EXTRA_GRAB: u8 = 1;
+const EXTRA_PARAMS: u8 = 2;
+const EXTRA_TIME_SCALE: u8 = 4;
+const EXTRA_RUN: u8 = 8;
+
+// How far past our next tick a peer's input can honestly be. A peer can get input_delay ticks
+// ahead of us before it needs our input, and schedules its own input_delay past that, the
+// redundancy is slack. Anything further would only pile up waiting, so receive drops it.
+pub fn max_ahead(input_delay: u32) -> u32 {
+    input_delay.saturating_mul(2).saturating_add(REDUNDANCY as u32)
+}
+
+// What the server and client loops drive, so they don't care whether they run lockstep or rollback.
+pub trait InputSync {
+    fn add_local(&mut self, input: Input);
+    // The inputs message to send this frame.
+    fn schedule_local(&mut self) -> Vec<u8>;
+    fn receive(&mut self, peer: usize, inputs: &[(u32, Input)]);
+    // Steps the simulation as far as the inputs we hold allow.
+    fn advance(&mut self, simulation: &mut Simulation, controls: &ControlsConfig);
+    // World states at every desync::INTERVAL tick that can no longer change, since the last call.
+    fn take_settled(&mut self) -> Vec<(u32, WorldState)>;
+}
+
+pub struct Start {
+    pub rollback: bool,
+    pub peer: usize,
+    pub peers: usize,
+    pub session: u8,
+    pub input_delay: u32,
+    pub params: PhysicsParams, // The server's, which may have changed since it read its config.
+    pub controls: ControlsConfig, // Also the server's, input::apply has to push the same on every peer.
+    pub scene: String, // Scene file, everyone builds the world from the same one.
+    pub state: Option<WorldState>, // Put on top of the scene, when the server loaded a save.
+}
+
+pub enum Message {
+    Start(Start),
+    Inputs(usize, u8, Vec<(u32, Input)>), // Peer, session, inputs.
+}
+
+pub fn encode_start(start: &Start) -> Vec<u8> {
+    let mut buf = vec![];
+    buf.write_u8(START).unwrap();
+    buf.write_u8(start.rollback as u8).unwrap();
+    buf.write_u8(start.peer as u8).unwrap();
+    buf.write_u8(start.peers as u8).unwrap();
+    buf.write_u8(start.session).unwrap();
+    buf.write_u32::<LittleEndian>(start.input_delay).unwrap();
+    buf.extend_from_slice(&packet::encode_params(&start.params));
+    let c = &start.controls;
+    for v in [c.push_force, c.levitation_force, c.drag_stiffness, c.drag_damping].iter() {
+        buf.write_f32::<LittleEndian>(*v).unwrap();
+    }
+    buf.write_u32::<LittleEndian>(start.scene.len() as u32).unwrap();
+    buf.extend_from_slice(start.scene.as_bytes());
+    let state = start.state.as_ref().map_or(String::new(), |s| serde_json::to_string(s).unwrap());
+    buf.write_u32::<LittleEndian>(state.len() as u32).unwrap();
+    buf.extend_from_slice(state.as_bytes());
+    return buf;
+}
+
+pub fn encode_inputs(peer: usize, session: u8, inputs: &[(u32, Input)]) -> Vec<u8> {
+    let mut buf = vec![];
+    buf.write_u8(INPUTS).unwrap();
+    buf.write_u8(peer as u8).unwrap();
+    buf.write_u8(session).unwrap();
+    buf.write_u8(inputs.len() as u8).unwrap();
+    for &(tick, ref input) in inputs.iter() {
+        buf.write_u32::<LittleEndian>(tick).unwrap();
+        buf.write_u8(input.bits).unwrap();
+        let extras = if input.grab.is_some() { EXTRA_GRAB } else { 0 } |
+                     if input.params.is_some() { EXTRA_PARAMS } else { 0 } |
+                     if input.time_scale.is_some() { EXTRA_TIME_SCALE } else { 0 } |
+                     if input.run.is_some() { EXTRA_RUN } else { 0 };
+        buf.write_u8(extras).unwrap();
+        if let Some(grab) = input.grab {
+            buf.write_u32::<LittleEndian>(grab.body as u32).unwrap();
+            for t in grab.target.iter() {
+                buf.write_f32::<LittleEndian>(*t).unwrap();
+            }
+        }
+        if let Some(ref params) = input.params {
+            buf.extend_from_slice(&packet::encode_params(params));
+        }
+        if let Some(scale) = input.time_scale {
+            buf.write_f32::<LittleEndian>(scale).unwrap();
+        }
+        if let Some(steps) = input.run {
+            buf.write_u32::<LittleEndian>(steps).unwrap();
+        }
+    }
+    return buf;
+}
+
+// None for anything that isn't a well formed lockstep message.
+pub fn decode(buf: &[u8]) -> Option<Message> {
+    decode_message(buf).unwrap_or(None)
+}
+
+fn decode_message(buf: &[u8]) -> io::Result<Option<Message>> {
+    let mut input = Cursor::new(buf);
+    match try!(input.read_u8()) {
+        START => {
+            let rollback = try!(input.read_u8()) != 0;
+            let peer = try!(input.read_u8()) as usize;
+            let peers = try!(input.read_u8()) as usize;
+            let session = try!(input.read_u8());
+            let input_delay = try!(input.read_u32::<LittleEndian>());
+            let params = try!(read_params(&mut input));
+            let mut v = [0.0; 4];
+            for x in v.iter_mut() {
+                *x = try!(input.read_f32::<LittleEndian>());
+            }
+            let controls = ControlsConfig { push_force: v[0], levitation_force: v[1], drag_stiffness: v[2], drag_damping: v[3] };
+            try!(controls.check().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+            let len = try!(input.read_u32::<LittleEndian>()) as usize;
+            if len > buf.len() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "scene longer than the message"));
+            }
+            let mut scene = vec![0; len];
+            try!(input.read_exact(&mut scene));
+            let scene = try!(String::from_utf8(scene).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+            let len = try!(input.read_u32::<LittleEndian>()) as usize;
+            if len > buf.len() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "state longer than the message"));
+            }
+            let mut state = vec![0; len];
+            try!(input.read_exact(&mut state));
+            let state = if len == 0 {
+                None
+            } else {
+                Some(try!(serde_json::from_slice(&state).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))))
+            };
+            Ok(Some(Message::Start(Start { rollback: rollback, peer: peer, peers: peers, session: session, input_delay: input_delay,
+                                           params: params, controls: controls, scene: scene, state: state })))
+        }
+        INPUTS => {
+            let peer = try!(input.read_u8()) as usize;
+            let session = try!(input.read_u8());
+            let count = try!(input.read_u8()) as usize;
+            let mut inputs = Vec::with_capacity(count);
+            for _ in 0..count {
+                let tick = try!(input.read_u32::<LittleEndian>());
+                let bits = try!(input.read_u8());
+                let extras = try!(input.read_u8());
+                let grab = if extras & EXTRA_GRAB != 0 {
+                    let body = try!(input.read_u32::<LittleEndian>()) as usize;
+                    let target = [try!(input.read_f32::<LittleEndian>()),
+                                  try!(input.read_f32::<LittleEndian>()),
+                                  try!(input.read_f32::<LittleEndian>())];
+                    Some(Grab { body: body, target: target })
+                } else {
+                    None
+                };
+                let params = if extras & EXTRA_PARAMS != 0 {
+                    Some(try!(read_params(&mut input)))
+                } else {
+                    None
+                };
+                let time_scale = if extras & EXTRA_TIME_SCALE != 0 {
+                    Some(try!(input.read_f32::<LittleEndian>()))
+                } else {
+                    None
+                };
+                let run = if extras & EXTRA_RUN != 0 {
+                    Some(try!(input.read_u32::<LittleEndian>()))
+                } else {
+                    None
+                };
+                inputs.push((tick, Input { bits: bits, grab: grab, params: params, time_scale: time_scale, run: run }));
+            }
+            Ok(Some(Message::Inputs(peer, session, inputs)))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn read_params(input: &mut Cursor<&[u8]>) -> io::Result<PhysicsParams> {
+    packet::read_params(input).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+}
+
+// Collects every peer's inputs per tick and only lets the simulation advance once a tick
+// has all of them. Local inputs are scheduled `input_delay` ticks ahead so they have time to
+// reach everyone else before that tick comes up.
+pub struct Lockstep {
+    pub tick: u32, // Next tick to simulate.
+    pub peer: usize,
+    peers: usize,
+    session: u8,
+    input_delay: u32,
+    inputs: BTreeMap<u32, Vec<Option<Input>>>,
+    pending: Input, // Gathered since we last scheduled a local input.
+    next_local: u32, // Tick the next local input is scheduled for.
+    sent: Vec<(u32, Input)>,
+    stalled_since: Option<Instant>,
+    settled: Vec<(u32, WorldState)>,
+}
+
+impl Lockstep {
+    pub fn new(peer: usize, peers: usize, session: u8, input_delay: u32) -> Lockstep {
+        let mut lockstep = Lockstep {
+            tick: 0,
+            peer: peer,
+            peers: peers,
+            session: session,
+            input_delay: input_delay,
+            inputs: BTreeMap::new(),
+            pending: Input::default(),
+            next_local: input_delay,
+            sent: Vec::new(),
+            stalled_since: None,
+            settled: Vec::new(),
+        };
+        // Nobody can have input for the first ticks, they run empty.
+        for t in 0..input_delay {
+            lockstep.inputs.insert(t, vec![Some(Input::default()); peers]);
+        }
+        return lockstep;
+    }
+
+    fn set(&mut self, tick: u32, peer: usize, input: Input) {
+        let peers = self.peers;
+        self.inputs.entry(tick).or_insert_with(|| vec![None; peers])[peer] = Some(input);
+    }
+
+    // Everyone's input for the current tick, in peer order, or None while we wait on someone.
+    pub fn ready(&mut self) -> Option<Vec<Input>> {
+        let complete = match self.inputs.get(&self.tick) {
+            Some(inputs) => inputs.iter().all(|i| i.is_some()),
+            None => false,
+        };
+        if !complete {
+            if self.stalled_since.is_none() {
+                self.stalled_since = Some(Instant::now());
+            }
+            return None;
+        }
+        if let Some(since) = self.stalled_since.take() {
+            let waited = since.elapsed();
+            if waited.as_secs() >= 1 {
+                println!("Resumed tick {} after waiting {}s on inputs.", self.tick, waited.as_secs());
+            }
+        }
+        let inputs = self.inputs.remove(&self.tick).unwrap();
+        self.tick += 1;
+        return Some(inputs.into_iter().map(|i| i.unwrap()).collect());
+    }
+
+    // Who we are stuck on and for how long, for the stall message.
+    pub fn stalled(&self) -> Option<(Vec<usize>, u64)> {
+        self.stalled_since.map(|since| {
+            let missing = match self.inputs.get(&self.tick) {
+                Some(inputs) => (0..self.peers).filter(|&p| inputs[p].is_none()).collect(),
+                None => (0..self.peers).collect(),
+            };
+            (missing, since.elapsed().as_secs())
+        })
+    }
+}
+
+impl InputSync for Lockstep {
+    fn add_local(&mut self, input: Input) {
+        self.pending.merge(input);
+    }
+
+    // Schedules what we gathered for the next free tick, unless we are already a full input
+    // delay ahead of the simulation. Returns the message to send to every other peer.
+    fn schedule_local(&mut self) -> Vec<u8> {
+        if self.next_local < self.tick + self.input_delay.max(1) {
+            let (tick, input, peer) = (self.next_local, self.pending, self.peer);
+            self.set(tick, peer, input);
+            self.sent.push((tick, input));
+            if self.sent.len() > REDUNDANCY {
+                self.sent.remove(0);
+            }
+            self.pending = Input::default();
+            self.next_local += 1;
+        }
+
+        return encode_inputs(self.peer, self.session, &self.sent);
+    }
+
+    fn receive(&mut self, peer: usize, inputs: &[(u32, Input)]) {
+        if peer >= self.peers || peer == self.peer {
+            return;
+        }
+        let last = self.tick.saturating_add(max_ahead(self.input_delay));
+        for &(tick, input) in inputs.iter() {
+            if tick >= self.tick && tick <= last { // Older ones are repeats of ticks we already ran.
+                self.set(tick, peer, input);
+            }
+        }
+    }
+
+    fn advance(&mut self, simulation: &mut Simulation, controls: &ControlsConfig) {
+        while let Some(inputs) = self.ready() {
+            for (peer, input) in inputs.into_iter().enumerate() {
+                input::apply(simulation, peer, input, controls);
+            }
+            simulation.step();
+            if self.tick % desync::INTERVAL == 0 {
+                self.settled.push((self.tick, simulation.snapshot())); // Every tick we ran is final.
+            }
+        }
+        if let Some((missing, secs)) = self.stalled() {
+            if secs >= 1 {
+                println!("Tick {} waiting {}s on inputs from peers {:?}.", self.tick, secs, missing);
+            }
+        }
+    }
+
+    fn take_settled(&mut self) -> Vec<(u32, WorldState)> {
+        std::mem::replace(&mut self.settled, Vec::new())
+    }
+}
@@ -0,0 +1,87 @@
+#![allow(dead_code)]
+
+use std;
+
+// How a surface feels to whatever touches it. Two colliding geoms get one contact surface
+// made by `combine`, so a material only says half of what happens.
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Material {
+    pub friction: f32, // Coulomb mu, scaled by the contact's normal force. Infinite never slips.
+    pub restitution: f32, // 0 stops dead, 1 bounces back as fast as it came.
+    pub soft_erp: Option<f32>, // Contact ERP and CFM, None for the world's.
+    pub soft_cfm: Option<f32>,
+    pub rolling_friction: f32, // Slows spheres and cylinders that would otherwise roll forever.
+}
+
+// What every geom got before there were materials, rough and dead.
+impl Default for Material {
+    fn default() -> Material {
+        Material {
+            friction: std::f32::INFINITY,
+            restitution: 0.0,
+            soft_erp: None,
+            soft_cfm: None,
+            rolling_friction: 0.0,
+        }
+    }
+}
+
+fn positive_or_zero(name: &str, v: f32) -> Result<(), String> {
+    if v >= 0.0 && !v.is_nan() {
+        Ok(())
+    } else {
+        Err(format!("{} {} is negative", name, v))
+    }
+}
+
+impl Material {
+    pub fn check(&self) -> Result<(), String> {
+        try!(positive_or_zero("friction", self.friction));
+        try!(positive_or_zero("rolling friction", self.rolling_friction));
+        if !(self.restitution >= 0.0 && self.restitution <= 1.0) {
+            return Err(format!("restitution {} is not between 0 and 1", self.restitution));
+        }
+        if let Some(erp) = self.soft_erp {
+            if !(erp >= 0.0 && erp <= 1.0) {
+                return Err(format!("soft erp {} is not between 0 and 1", erp));
+            }
+        }
+        if let Some(cfm) = self.soft_cfm {
+            try!(positive_or_zero("soft cfm", cfm));
+        }
+        return Ok(());
+    }
+}
+
+// The surface two materials make together:
+//   friction is the geometric mean, so ice on anything is slippery. Infinite friction takes
+//     the other side's, the default ground doesn't hide ice.
+//   restitution is the bouncier of the two, a ball bounces on the floor.
+//   softness is the softer of the two, a small ERP and a big CFM.
+//   rolling friction is the mean.
+// Order doesn't matter, combine(a, b) == combine(b, a).
+pub fn combine(a: &Material, b: &Material) -> Material {
+    let friction = if a.friction.is_infinite() {
+        b.friction
+    } else if b.friction.is_infinite() {
+        a.friction
+    } else {
+        (a.friction * b.friction).sqrt()
+    };
+    return Material {
+        friction: friction,
+        restitution: a.restitution.max(b.restitution),
+        soft_erp: softer(a.soft_erp, b.soft_erp, f32::min),
+        soft_cfm: softer(a.soft_cfm, b.soft_cfm, f32::max),
+        rolling_friction: (a.rolling_friction + b.rolling_friction) / 2.0,
+    };
+}
+
+fn softer(a: Option<f32>, b: Option<f32>, pick: fn(f32, f32) -> f32) -> Option<f32> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(pick(a, b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
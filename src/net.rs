@@ -0,0 +1,223 @@
+#![allow(dead_code)]
+
+extern crate libc;
+
+use std;
+use std::io;
+use std::time::Duration;
+use std::thread;
+use std::sync::mpsc::{channel, Sender, Receiver, TryRecvError};
+use std::net::{UdpSocket, SocketAddr, SocketAddrV4, SocketAddrV6, IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
+
+// Listen on every interface. We prefer a single IPv6 socket with IPV6_V6ONLY cleared so IPv4
+// peers show up as ::ffff:a.b.c.d, and fall back to plain IPv4 if the OS won't give us that.
+pub fn bind_dual_stack(port: u16) -> io::Result<UdpSocket> {
+    match bind_v6_any(port) {
+        Ok(socket) => Ok(socket),
+        Err(e) => {
+            println!("Dual-stack IPv6 unavailable ({}), listening on IPv4 only.", e);
+            UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port))
+        }
+    }
+}
+
+#[cfg(unix)]
+fn bind_v6_any(port: u16) -> io::Result<UdpSocket> {
+    use std::os::unix::io::FromRawFd;
+    unsafe {
+        let fd = libc::socket(libc::AF_INET6, libc::SOCK_DGRAM, 0);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Has to happen before bind, std doesn't give us a hook for that.
+        let off: libc::c_int = 0;
+        let mut err = libc::setsockopt(fd, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY,
+                                       &off as *const _ as *const libc::c_void,
+                                       std::mem::size_of::<libc::c_int>() as libc::socklen_t);
+        if err == 0 {
+            let mut addr: libc::sockaddr_in6 = std::mem::zeroed(); // Zeroed sin6_addr is in6addr_any
+            addr.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            addr.sin6_port = port.to_be();
+            err = libc::bind(fd, &addr as *const _ as *const libc::sockaddr,
+                             std::mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t);
+        }
+        if err != 0 {
+            let e = io::Error::last_os_error();
+            libc::close(fd);
+            return Err(e);
+        }
+        return Ok(UdpSocket::from_raw_fd(fd));
+    }
+}
+
+#[cfg(not(unix))]
+fn bind_v6_any(port: u16) -> io::Result<UdpSocket> {
+    // Windows defaults to v6 only and we have no setsockopt here, so this only takes IPv6 peers.
+    UdpSocket::bind(SocketAddrV6::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0), port, 0, 0))
+}
+
+// Bind the unspecified address of the same family as `remote`, sockets can only talk to their own family.
+pub fn bind_for(remote: &SocketAddr, port: u16) -> io::Result<UdpSocket> {
+    match *remote {
+        SocketAddr::V4(_) => UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port)),
+        SocketAddr::V6(_) => UdpSocket::bind(SocketAddrV6::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0), port, 0, 0)),
+    }
+}
+
+// Accepts "host", "host:port", "1.2.3.4", "::1", "[::1]" and "[::1]:port".
+pub fn resolve(host: &str, default_port: u16) -> io::Result<Vec<SocketAddr>> {
+    if let Ok(addr) = host.parse::<SocketAddr>() {
+        return Ok(vec![addr]);
+    }
+    if let Ok(ip) = host.trim_left_matches('[').trim_right_matches(']').parse::<IpAddr>() {
+        return Ok(vec![SocketAddr::new(ip, default_port)]);
+    }
+    let addrs: Vec<SocketAddr> = if host.contains(':') {
+        try!(host.to_socket_addrs()).collect()
+    } else {
+        try!((host, default_port).to_socket_addrs()).collect()
+    };
+    if addrs.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve to any address", host)));
+    }
+    return Ok(addrs);
+}
+
+// A dual-stack socket reports IPv4 peers as ::ffff:a.b.c.d. Anything that keys on a peer
+// address should go through this so the same client is not seen twice.
+pub fn canonical(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => {
+            let s = v6.ip().segments();
+            if s[0] == 0 && s[1] == 0 && s[2] == 0 && s[3] == 0 && s[4] == 0 && s[5] == 0xffff {
+                let ip = Ipv4Addr::new((s[6] >> 8) as u8, s[6] as u8, (s[7] >> 8) as u8, s[7] as u8);
+                SocketAddr::V4(SocketAddrV4::new(ip, v6.port()))
+            } else {
+                addr
+            }
+        }
+        SocketAddr::V4(_) => addr,
+    }
+}
+
+pub fn same_peer(a: SocketAddr, b: SocketAddr) -> bool {
+    canonical(a) == canonical(b)
+}
+
+// Biggest thing UDP can carry. Init packets hold the whole scene, so they can get large.
+pub const MAX_DATAGRAM: usize = 65536;
+
+// Most a datagram can actually hold over IPv4, 65535 less the IP and UDP headers. Anything we
+// send in one piece has to fit, a bigger send just fails.
+pub const MAX_PAYLOAD: usize = 65507;
+
+// Try each resolved address in turn, the first one that answers the hello wins.
+// Returns the socket, the server address and the first packet it sent us.
+pub fn connect(host: &str, port: u16, local_port: u16, hello: &[u8]) -> io::Result<(UdpSocket, SocketAddr, Vec<u8>)> {
+    let mut last_err = io::Error::new(io::ErrorKind::NotFound, format!("No address to connect to for {}", host));
+    for addr in try!(resolve(host, port)) {
+        println!("Trying {}.", addr);
+        let socket = match bind_for(&addr, local_port) {
+            Ok(s) => s,
+            Err(e) => { last_err = e; continue; }
+        };
+        let mut buf = [0; MAX_DATAGRAM];
+        try!(socket.set_read_timeout(Some(Duration::from_secs(2))));
+        if let Err(e) = socket.send_to(hello, addr) {
+            last_err = e;
+            continue;
+        }
+        loop {
+            match socket.recv_from(&mut buf) {
+                Ok((amt, from)) => {
+                    if !same_peer(from, addr) {
+                        continue; // Someone else, keep waiting on the server.
+                    }
+                    try!(socket.set_read_timeout(None));
+                    return Ok((socket, addr, buf[..amt].to_vec()));
+                }
+                Err(e) => { last_err = e; break; }
+            }
+        }
+    }
+    return Err(last_err);
+}
+
+// Errors a UDP socket hands back now and then that say nothing about the socket itself.
+fn transient(e: &io::Error) -> bool {
+    match e.kind() {
+        io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset | io::ErrorKind::Interrupted |
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => true,
+        _ => false,
+    }
+}
+
+// Socket I/O on its own threads so the render loop never blocks on the network.
+// Datagrams show up on `incoming` in arrival order, anything put on `outgoing` gets sent.
+pub struct NetThread {
+    incoming: Receiver<(Vec<u8>, SocketAddr)>,
+    outgoing: Sender<(Vec<u8>, SocketAddr)>,
+}
+
+impl NetThread {
+    pub fn spawn(socket: UdpSocket) -> NetThread {
+        let (in_tx, in_rx) = channel();
+        let (out_tx, out_rx) = channel::<(Vec<u8>, SocketAddr)>();
+        let send_socket = socket.try_clone().unwrap();
+
+        thread::spawn(move || {
+            let mut buf = [0; MAX_DATAGRAM];
+            let mut failures = 0u32; // In a row.
+            loop {
+                match socket.recv_from(&mut buf) {
+                    Ok((amt, from)) => {
+                        failures = 0;
+                        if in_tx.send((buf[..amt].to_vec(), from)).is_err() {
+                            return; // Main loop hung up.
+                        }
+                    }
+                    // Leftover ICMP errors from a peer that went away, nothing to do but keep
+                    // listening. They can come back as fast as we ask, so say so now and then
+                    // and slow down while they last.
+                    Err(ref e) if transient(e) => {
+                        failures += 1;
+                        if failures.is_power_of_two() {
+                            println!("Receive failed {} times in a row: {}", failures, e);
+                        }
+                        if failures > 8 {
+                            thread::sleep(Duration::from_millis(std::cmp::min(failures as u64, 100)));
+                        }
+                    }
+                    // Anything else won't get better, poll() reports the thread is gone.
+                    Err(e) => {
+                        println!("Receive failed, giving up: {}", e);
+                        return;
+                    }
+                }
+            }
+        });
+        thread::spawn(move || {
+            for (data, to) in out_rx.iter() {
+                if let Err(e) = send_socket.send_to(&data, to) {
+                    println!("Send to {} failed: {}", to, e);
+                }
+            }
+        });
+
+        return NetThread { incoming: in_rx, outgoing: out_tx };
+    }
+
+    // Next datagram that has already arrived, never blocks.
+    pub fn poll(&self) -> Option<(Vec<u8>, SocketAddr)> {
+        match self.incoming.try_recv() {
+            Ok(packet) => Some(packet),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => panic!("Network receive thread died"),
+        }
+    }
+
+    pub fn send(&self, data: Vec<u8>, to: SocketAddr) {
+        self.outgoing.send((data, to)).unwrap();
+    }
+}
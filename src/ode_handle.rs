@@ -0,0 +1,173 @@
+#![allow(dead_code)]
+
+extern crate ode;
+extern crate libc;
+
+use std;
+use ode::*;
+
+// Owning wrappers around ODE's handles, each one destroys what it holds when dropped. ODE
+// cares about the order: geoms before the trimesh data they read, joints before their world,
+// the space and world before dCloseODE. Simulation gets that by declaring its fields in that
+// order, Rust drops them top to bottom.
+
+// ODE itself. dInitODE and dCloseODE are counted, so a second Simulation made while the first
+// is still around (loading a save) doesn't close ODE under it.
+pub struct Library(());
+
+impl Library {
+    pub fn init() -> Library {
+        unsafe {
+            ode::dInitODE();
+        }
+        Library(())
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        unsafe {
+            ode::dCloseODE();
+        }
+    }
+}
+
+pub struct World(dWorldID);
+
+impl World {
+    pub fn new() -> World {
+        unsafe { World(ode::dWorldCreate()) }
+    }
+
+    pub fn id(&self) -> dWorldID {
+        self.0
+    }
+}
+
+impl Drop for World {
+    fn drop(&mut self) {
+        unsafe {
+            ode::dWorldDestroy(self.0);
+        }
+    }
+}
+
+pub struct Space(dSpaceID);
+
+impl Space {
+    pub fn new() -> Space {
+        unsafe {
+            let space = ode::dHashSpaceCreate(std::ptr::null_mut());
+            ode::dSpaceSetCleanup(space, 0); // Its geoms are Geoms, they destroy themselves.
+            Space(space)
+        }
+    }
+
+    pub fn id(&self) -> dSpaceID {
+        self.0
+    }
+}
+
+impl Drop for Space {
+    fn drop(&mut self) {
+        unsafe {
+            ode::dSpaceDestroy(self.0);
+        }
+    }
+}
+
+pub struct JointGroup(dJointGroupID);
+
+impl JointGroup {
+    pub fn new() -> JointGroup {
+        unsafe { JointGroup(ode::dJointGroupCreate(0)) }
+    }
+
+    pub fn id(&self) -> dJointGroupID {
+        self.0
+    }
+}
+
+impl Drop for JointGroup {
+    fn drop(&mut self) {
+        unsafe {
+            ode::dJointGroupDestroy(self.0);
+        }
+    }
+}
+
+// Has to drop before its world.
+pub struct Body(dBodyID);
+
+impl Body {
+    pub fn new(world: &World) -> Body {
+        unsafe { Body(ode::dBodyCreate(world.id())) }
+    }
+
+    pub fn id(&self) -> dBodyID {
+        self.0
+    }
+}
+
+impl Drop for Body {
+    fn drop(&mut self) {
+        unsafe {
+            ode::dBodyDestroy(self.0); // Joints on it are left attached to nothing.
+        }
+    }
+}
+
+// Has to drop before its space, and before the trimesh data if it is a trimesh.
+pub struct Geom(dGeomID);
+
+impl Geom {
+    // Takes over a geom straight out of one of ODE's dCreate functions, nothing else may
+    // destroy it after.
+    pub unsafe fn from_id(geom: dGeomID) -> Geom {
+        Geom(geom)
+    }
+
+    pub fn id(&self) -> dGeomID {
+        self.0
+    }
+}
+
+impl Drop for Geom {
+    fn drop(&mut self) {
+        unsafe {
+            ode::dGeomDestroy(self.0); // Takes it out of its space too.
+        }
+    }
+}
+
+// ODE keeps pointers into the vertices and triangles rather than copying them, so this keeps
+// its own copy of both. Their heap buffers stay put when it moves, and nothing else can get at
+// them to change or free them.
+pub struct TriMeshData {
+    id: dTriMeshDataID,
+    vertices: Vec<[f32; 3]>,
+    triangles: Vec<[u32; 3]>,
+}
+
+impl TriMeshData {
+    pub unsafe fn build(vertices: &[[f32; 3]], triangles: &[[u32; 3]]) -> TriMeshData {
+        let (vertices, triangles) = (vertices.to_vec(), triangles.to_vec());
+        let data = ode::dGeomTriMeshDataCreate();
+        ode::dGeomTriMeshDataBuildSingle(data,
+            vertices.as_ptr() as *const libc::c_void, std::mem::size_of::<[f32; 3]>() as i32, vertices.len() as i32,
+            triangles.as_ptr() as *const libc::c_void, (triangles.len() * 3) as i32, std::mem::size_of::<[u32; 3]>() as i32);
+        TriMeshData { id: data, vertices: vertices, triangles: triangles }
+    }
+
+    pub fn id(&self) -> dTriMeshDataID {
+        self.id
+    }
+}
+
+impl Drop for TriMeshData {
+    fn drop(&mut self) {
+        unsafe {
+            ode::dGeomTriMeshDataDestroy(self.id);
+        }
+    }
+}
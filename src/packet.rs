@@ -0,0 +1,454 @@
+#![allow(dead_code)]
+
+extern crate byteorder;
+
+use std;
+use std::fmt;
+use std::io::Cursor;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use shape::Shape;
+use joint::JointLine;
+use collision::{CollisionEvent, Phase};
+use params::PhysicsParams;
+
+// Snapshot layout, as written by Simulation::serialize:
+//   u8 paused, u8 init
+//   only when not paused or init:
+//     u32 geom count
+//     per geom: u8 moving, when moving (or init) 3 f32 position, 12 f32 rotation
+//               and on init frames an f32 mass
+//   then any number of extensions: u8 tag, u16 length, length bytes
+// Decoders skip extensions they don't know, so new ones can be added without breaking old clients.
+
+pub const EXT_TICK: u8 = 1; // u32 server tick the snapshot was taken at.
+pub const EXT_CHECKSUM: u8 = 2; // desync::Checksum of the world at that tick.
+pub const EXT_SCENE: u8 = 3; // Scene file text, on init packets.
+pub const EXT_SHAPES: u8 = 4; // Every body's shape, on init packets. See encode_shapes.
+pub const EXT_JOINTS: u8 = 5; // Where the joints are, for drawing. See encode_joint_lines.
+pub const EXT_COLLISIONS: u8 = 6; // Hits since the last snapshot. See encode_collisions.
+pub const EXT_PARAMS: u8 = 7; // The server's PhysicsParams, on init packets and for a while after a change.
+pub const EXT_STEPS: u8 = 8; // u32 physics steps the server takes between this snapshot and the next.
+
+#[derive(PartialEq, Debug)]
+pub struct CubePose {
+    pub pos: [f32; 3],
+    pub rot: [f32; 12], // 3 rows, 4 columns.
+    pub mass: Option<f32>,
+}
+
+pub struct Snapshot {
+    pub paused: bool,
+    pub init: bool,
+    pub cubes: Vec<Option<CubePose>>, // None for cubes at rest.
+    pub extensions: Vec<(u8, Vec<u8>)>,
+    pub bytes: ByteCount,
+}
+
+impl Snapshot {
+    pub fn extension(&self, tag: u8) -> Option<&[u8]> {
+        self.extensions.iter().find(|e| e.0 == tag).map(|e| &e.1[..])
+    }
+}
+
+// Where the bytes of a packet went.
+#[derive(Default, Clone, Copy)]
+pub struct ByteCount {
+    pub header: usize,
+    pub geom_count: usize,
+    pub moving_flags: usize,
+    pub position: usize,
+    pub rotation: usize,
+    pub mass: usize,
+    pub extensions: usize,
+}
+
+impl ByteCount {
+    pub fn total(&self) -> usize {
+        self.header + self.geom_count + self.moving_flags + self.position + self.rotation + self.mass + self.extensions
+    }
+
+    pub fn add(&mut self, other: &ByteCount) {
+        self.header += other.header;
+        self.geom_count += other.geom_count;
+        self.moving_flags += other.moving_flags;
+        self.position += other.position;
+        self.rotation += other.rotation;
+        self.mass += other.mass;
+        self.extensions += other.extensions;
+    }
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Truncated(&'static str, usize), // Ran out of bytes reading this field at this offset.
+    TooManyGeoms(u32, usize), // Claimed count, bytes left to hold them.
+    NotFinite(&'static str, usize), // NaN or infinity in this field at this offset.
+    BadMass(f32),
+    BadShape(String),
+    BadParams(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::Truncated(field, offset) => write!(f, "packet truncated reading {} at byte {}", field, offset),
+            DecodeError::TooManyGeoms(count, left) => write!(f, "{} geoms can't fit in the {} bytes left", count, left),
+            DecodeError::NotFinite(field, offset) => write!(f, "{} is not finite at byte {}", field, offset),
+            DecodeError::BadMass(mass) => write!(f, "mass {} is not positive", mass),
+            DecodeError::BadShape(ref e) => write!(f, "bad shape: {}", e),
+            DecodeError::BadParams(ref e) => write!(f, "bad physics params: {}", e),
+        }
+    }
+}
+
+fn truncated(input: &Cursor<&[u8]>, field: &'static str) -> DecodeError {
+    DecodeError::Truncated(field, input.position() as usize)
+}
+
+// Anything we hand to ODE has to be finite, a single NaN spreads through the whole island.
+fn read_finite(input: &mut Cursor<&[u8]>, field: &'static str) -> Result<f32, DecodeError> {
+    let v = try!(input.read_f32::<LittleEndian>().map_err(|_| truncated(input, field)));
+    if !v.is_finite() {
+        return Err(DecodeError::NotFinite(field, input.position() as usize - 4));
+    }
+    return Ok(v);
+}
+
+pub fn decode(buf: &[u8]) -> Result<Snapshot, DecodeError> {
+    let mut input = Cursor::new(buf);
+    let mut bytes = ByteCount { ..Default::default() };
+
+    let paused = try!(input.read_u8().map_err(|_| truncated(&input, "paused flag"))) != 0u8;
+    let init = try!(input.read_u8().map_err(|_| truncated(&input, "init flag"))) != 0u8;
+    bytes.header = 2;
+
+    let mut cubes = Vec::new();
+    if !paused || init {
+        let num_geoms = try!(input.read_u32::<LittleEndian>().map_err(|_| truncated(&input, "geom count"))) as usize;
+        bytes.geom_count = 4;
+        let left = buf.len() - input.position() as usize;
+        if num_geoms > left { // Every geom takes at least its moving flag.
+            return Err(DecodeError::TooManyGeoms(num_geoms as u32, left));
+        }
+        for _ in 0..num_geoms {
+            let moving = try!(input.read_u8().map_err(|_| truncated(&input, "moving flag")));
+            bytes.moving_flags += 1;
+            if moving == 0 {
+                cubes.push(None); //This cube has no velocity and it is not an init frame
+                                  // so no data for it follows.
+                continue;
+            }
+
+            let mut pos = [0f32; 3];
+            let mut rot = [0f32; 12];
+            for p in 0..3 {
+                pos[p] = try!(read_finite(&mut input, "position"));
+            }
+            bytes.position += 12;
+            for r in 0..12 {
+                rot[r] = try!(read_finite(&mut input, "rotation"));
+            }
+            bytes.rotation += 48;
+            let mut mass = None;
+            if init {
+                let m = try!(read_finite(&mut input, "mass"));
+                if m <= 0.0 { // ODE asserts on non positive masses.
+                    return Err(DecodeError::BadMass(m));
+                }
+                mass = Some(m);
+                bytes.mass += 4;
+            }
+            cubes.push(Some(CubePose { pos: pos, rot: rot, mass: mass }));
+        }
+    }
+
+    let mut extensions = Vec::new();
+    while (input.position() as usize) < buf.len() {
+        let tag = try!(input.read_u8().map_err(|_| truncated(&input, "extension tag")));
+        let len = try!(input.read_u16::<LittleEndian>().map_err(|_| truncated(&input, "extension length"))) as usize;
+        let start = input.position() as usize;
+        if start + len > buf.len() {
+            return Err(DecodeError::Truncated("extension", start));
+        }
+        extensions.push((tag, buf[start..start+len].to_vec()));
+        input.set_position((start + len) as u64);
+        bytes.extensions += 3 + len;
+    }
+
+    return Ok(Snapshot { paused: paused, init: init, cubes: cubes, extensions: extensions, bytes: bytes });
+}
+
+pub fn push_extension(buf: &mut Vec<u8>, tag: u8, payload: &[u8]) {
+    buf.write_u8(tag).unwrap();
+    buf.write_u16::<LittleEndian>(payload.len() as u16).unwrap();
+    buf.extend_from_slice(payload);
+}
+
+// The inverse of decode, for tools that need to build packets by hand.
+pub fn encode(paused: bool, init: bool, cubes: &[Option<CubePose>], extensions: &[(u8, Vec<u8>)]) -> Vec<u8> {
+    let mut buf = vec![];
+    buf.write_u8(paused as u8).unwrap();
+    buf.write_u8(init as u8).unwrap();
+    if !paused || init {
+        buf.write_u32::<LittleEndian>(cubes.len() as u32).unwrap();
+        for cube in cubes.iter() {
+            match *cube {
+                None => buf.write_u8(0).unwrap(),
+                Some(ref c) => {
+                    buf.write_u8(1).unwrap();
+                    for p in 0..3 {
+                        buf.write_f32::<LittleEndian>(c.pos[p]).unwrap();
+                    }
+                    for r in 0..12 {
+                        buf.write_f32::<LittleEndian>(c.rot[r]).unwrap();
+                    }
+                    if init {
+                        buf.write_f32::<LittleEndian>(c.mass.unwrap_or(1.0)).unwrap();
+                    }
+                }
+            }
+        }
+    }
+    for &(tag, ref payload) in extensions.iter() {
+        push_extension(&mut buf, tag, payload);
+    }
+    return buf;
+}
+
+// Shapes extension layout:
+//   u32 count, then per shape a u8 type followed by
+//     0 box: 3 f32 size   1 sphere: f32 radius   2 capsule, 3 cylinder: f32 radius, f32 length
+//     4 trimesh: u32 vertex count, 3 f32 per vertex, u32 triangle count, 3 u32 indices per triangle
+pub fn encode_shapes(shapes: &[Shape]) -> Vec<u8> {
+    let mut buf = vec![];
+    buf.write_u32::<LittleEndian>(shapes.len() as u32).unwrap();
+    for shape in shapes.iter() {
+        match *shape {
+            Shape::Box { size } => {
+                buf.write_u8(0).unwrap();
+                for s in size.iter() {
+                    buf.write_f32::<LittleEndian>(*s).unwrap();
+                }
+            }
+            Shape::Sphere { radius } => {
+                buf.write_u8(1).unwrap();
+                buf.write_f32::<LittleEndian>(radius).unwrap();
+            }
+            Shape::Capsule { radius, length } | Shape::Cylinder { radius, length } => {
+                buf.write_u8(if let Shape::Capsule { .. } = *shape { 2 } else { 3 }).unwrap();
+                buf.write_f32::<LittleEndian>(radius).unwrap();
+                buf.write_f32::<LittleEndian>(length).unwrap();
+            }
+            Shape::Trimesh { ref vertices, ref triangles } => {
+                buf.write_u8(4).unwrap();
+                buf.write_u32::<LittleEndian>(vertices.len() as u32).unwrap();
+                for v in vertices.iter() {
+                    for c in v.iter() {
+                        buf.write_f32::<LittleEndian>(*c).unwrap();
+                    }
+                }
+                buf.write_u32::<LittleEndian>(triangles.len() as u32).unwrap();
+                for t in triangles.iter() {
+                    for i in t.iter() {
+                        buf.write_u32::<LittleEndian>(*i).unwrap();
+                    }
+                }
+            }
+        }
+    }
+    return buf;
+}
+
+// Shapes come out checked, safe to hand to ODE.
+pub fn decode_shapes(buf: &[u8]) -> Result<Vec<Shape>, DecodeError> {
+    let mut input = Cursor::new(buf);
+    let count = try!(input.read_u32::<LittleEndian>().map_err(|_| truncated(&input, "shape count"))) as usize;
+    if count > buf.len() { // At least the type byte each.
+        return Err(DecodeError::TooManyGeoms(count as u32, buf.len()));
+    }
+    let mut shapes = Vec::with_capacity(count);
+    for _ in 0..count {
+        let shape = match try!(input.read_u8().map_err(|_| truncated(&input, "shape type"))) {
+            0 => Shape::Box { size: [try!(read_finite(&mut input, "box size")),
+                                     try!(read_finite(&mut input, "box size")),
+                                     try!(read_finite(&mut input, "box size"))] },
+            1 => Shape::Sphere { radius: try!(read_finite(&mut input, "radius")) },
+            2 => Shape::Capsule { radius: try!(read_finite(&mut input, "radius")), length: try!(read_finite(&mut input, "length")) },
+            3 => Shape::Cylinder { radius: try!(read_finite(&mut input, "radius")), length: try!(read_finite(&mut input, "length")) },
+            4 => {
+                let vertex_count = try!(input.read_u32::<LittleEndian>().map_err(|_| truncated(&input, "vertex count"))) as usize;
+                if vertex_count * 12 > buf.len() {
+                    return Err(DecodeError::Truncated("vertices", input.position() as usize));
+                }
+                let mut vertices = Vec::with_capacity(vertex_count);
+                for _ in 0..vertex_count {
+                    vertices.push([try!(read_finite(&mut input, "vertex")),
+                                   try!(read_finite(&mut input, "vertex")),
+                                   try!(read_finite(&mut input, "vertex"))]);
+                }
+                let triangle_count = try!(input.read_u32::<LittleEndian>().map_err(|_| truncated(&input, "triangle count"))) as usize;
+                if triangle_count * 12 > buf.len() {
+                    return Err(DecodeError::Truncated("triangles", input.position() as usize));
+                }
+                let mut triangles = Vec::with_capacity(triangle_count);
+                for _ in 0..triangle_count {
+                    let mut t = [0u32; 3];
+                    for i in 0..3 {
+                        t[i] = try!(input.read_u32::<LittleEndian>().map_err(|_| truncated(&input, "triangle")));
+                    }
+                    triangles.push(t);
+                }
+                Shape::Trimesh { vertices: vertices, triangles: triangles }
+            }
+            t => return Err(DecodeError::BadShape(format!("unknown shape type {}", t))),
+        };
+        try!(shape.check().map_err(DecodeError::BadShape));
+        shapes.push(shape);
+    }
+    return Ok(shapes);
+}
+
+// Joints extension layout:
+//   u16 count, per joint u32 body1, u32 body2 (u32::MAX for the world), 3 f32 anchor
+pub fn encode_joint_lines(lines: &[JointLine]) -> Vec<u8> {
+    let mut buf = vec![];
+    buf.write_u16::<LittleEndian>(lines.len() as u16).unwrap();
+    for line in lines.iter() {
+        buf.write_u32::<LittleEndian>(line.body1 as u32).unwrap();
+        buf.write_u32::<LittleEndian>(line.body2.map_or(std::u32::MAX, |b| b as u32)).unwrap();
+        for a in line.anchor.iter() {
+            buf.write_f32::<LittleEndian>(*a).unwrap();
+        }
+    }
+    return buf;
+}
+
+pub fn decode_joint_lines(buf: &[u8]) -> Result<Vec<JointLine>, DecodeError> {
+    let mut input = Cursor::new(buf);
+    let count = try!(input.read_u16::<LittleEndian>().map_err(|_| truncated(&input, "joint count"))) as usize;
+    let mut lines = Vec::with_capacity(count);
+    for _ in 0..count {
+        let body1 = try!(input.read_u32::<LittleEndian>().map_err(|_| truncated(&input, "joint body"))) as usize;
+        let body2 = match try!(input.read_u32::<LittleEndian>().map_err(|_| truncated(&input, "joint body"))) {
+            std::u32::MAX => None,
+            b => Some(b as usize),
+        };
+        let anchor = [try!(read_finite(&mut input, "joint anchor")),
+                      try!(read_finite(&mut input, "joint anchor")),
+                      try!(read_finite(&mut input, "joint anchor"))];
+        lines.push(JointLine { body1: body1, body2: body2, anchor: anchor });
+    }
+    return Ok(lines);
+}
+
+// Collisions extension layout:
+//   u16 count, per event u8 phase (0 begin, 1 persist, 2 end), u32 body1,
+//   u32 body2 (u32::MAX for statics), 3 f32 point, 3 f32 normal, f32 impulse
+pub const MAX_COLLISIONS: usize = 256; // Keeps the extension well under its u16 length.
+
+pub fn encode_collisions(events: &[CollisionEvent]) -> Vec<u8> {
+    let events = &events[..std::cmp::min(events.len(), MAX_COLLISIONS)];
+    let mut buf = vec![];
+    buf.write_u16::<LittleEndian>(events.len() as u16).unwrap();
+    for e in events.iter() {
+        buf.write_u8(match e.phase { Phase::Begin => 0, Phase::Persist => 1, Phase::End => 2 }).unwrap();
+        buf.write_u32::<LittleEndian>(e.body1 as u32).unwrap();
+        buf.write_u32::<LittleEndian>(e.body2.map_or(std::u32::MAX, |b| b as u32)).unwrap();
+        for v in e.point.iter().chain(e.normal.iter()) {
+            buf.write_f32::<LittleEndian>(*v).unwrap();
+        }
+        buf.write_f32::<LittleEndian>(e.impulse).unwrap();
+    }
+    return buf;
+}
+
+pub fn decode_collisions(buf: &[u8]) -> Result<Vec<CollisionEvent>, DecodeError> {
+    let mut input = Cursor::new(buf);
+    let count = try!(input.read_u16::<LittleEndian>().map_err(|_| truncated(&input, "collision count"))) as usize;
+    let mut events = Vec::with_capacity(std::cmp::min(count, MAX_COLLISIONS));
+    for _ in 0..count {
+        let phase = match try!(input.read_u8().map_err(|_| truncated(&input, "collision phase"))) {
+            0 => Phase::Begin,
+            1 => Phase::Persist,
+            _ => Phase::End, // Nothing reads the phase past Begin, no point failing the packet.
+        };
+        let body1 = try!(input.read_u32::<LittleEndian>().map_err(|_| truncated(&input, "collision body"))) as usize;
+        let body2 = match try!(input.read_u32::<LittleEndian>().map_err(|_| truncated(&input, "collision body"))) {
+            std::u32::MAX => None,
+            b => Some(b as usize),
+        };
+        let mut v = [0.0; 7];
+        for x in v.iter_mut() {
+            *x = try!(read_finite(&mut input, "collision"));
+        }
+        events.push(CollisionEvent { phase: phase, body1: body1, body2: body2, point: [v[0], v[1], v[2]],
+                                     normal: [v[3], v[4], v[5]], impulse: v[6] });
+    }
+    return Ok(events);
+}
+
+// Params layout, also inside lockstep inputs:
+//   f32 gravity, cfm, erp, u32 iterations, f32 step size, contact surface layer,
+//   max correcting velocity, u8 max contacts, u8 auto disable, f32 auto disable linear,
+//   angular, u32 auto disable steps, f32 auto disable time
+pub fn encode_params(p: &PhysicsParams) -> Vec<u8> {
+    let mut buf = vec![];
+    for v in [p.gravity, p.cfm, p.erp].iter() {
+        buf.write_f32::<LittleEndian>(*v).unwrap();
+    }
+    buf.write_u32::<LittleEndian>(p.iterations).unwrap();
+    for v in [p.step_size, p.contact_surface_layer, p.max_correcting_vel].iter() {
+        buf.write_f32::<LittleEndian>(*v).unwrap();
+    }
+    buf.write_u8(p.max_contacts as u8).unwrap();
+    buf.write_u8(p.auto_disable as u8).unwrap();
+    buf.write_f32::<LittleEndian>(p.auto_disable_linear).unwrap();
+    buf.write_f32::<LittleEndian>(p.auto_disable_angular).unwrap();
+    buf.write_u32::<LittleEndian>(p.auto_disable_steps).unwrap();
+    buf.write_f32::<LittleEndian>(p.auto_disable_time).unwrap();
+    return buf;
+}
+
+fn read_f32(input: &mut Cursor<&[u8]>, field: &'static str) -> Result<f32, DecodeError> {
+    input.read_f32::<LittleEndian>().map_err(|_| truncated(input, field))
+}
+
+fn read_u32(input: &mut Cursor<&[u8]>, field: &'static str) -> Result<u32, DecodeError> {
+    input.read_u32::<LittleEndian>().map_err(|_| truncated(input, field))
+}
+
+// Reads from wherever input is, so lockstep can pull params out of the middle of a message.
+// Max correcting velocity may be infinite, so nothing goes through read_finite and
+// PhysicsParams::check has the final say.
+pub fn read_params(input: &mut Cursor<&[u8]>) -> Result<PhysicsParams, DecodeError> {
+    let params = PhysicsParams {
+        gravity: try!(read_f32(input, "gravity")),
+        cfm: try!(read_f32(input, "cfm")),
+        erp: try!(read_f32(input, "erp")),
+        iterations: try!(read_u32(input, "iterations")),
+        step_size: try!(read_f32(input, "step size")),
+        contact_surface_layer: try!(read_f32(input, "contact surface layer")),
+        max_correcting_vel: try!(read_f32(input, "max correcting velocity")),
+        max_contacts: try!(input.read_u8().map_err(|_| truncated(input, "max contacts"))) as usize,
+        auto_disable: try!(input.read_u8().map_err(|_| truncated(input, "auto disable"))) != 0,
+        auto_disable_linear: try!(read_f32(input, "auto disable linear")),
+        auto_disable_angular: try!(read_f32(input, "auto disable angular")),
+        auto_disable_steps: try!(read_u32(input, "auto disable steps")),
+        auto_disable_time: try!(read_f32(input, "auto disable time")),
+    };
+    try!(params.check().map_err(DecodeError::BadParams));
+    return Ok(params);
+}
+
+pub fn decode_params(buf: &[u8]) -> Result<PhysicsParams, DecodeError> {
+    return read_params(&mut Cursor::new(buf));
+}
+
+pub fn fmt_3(e: [f32; 3]) -> String {
+    format!("({},{},{})", e[0], e[1], e[2])
+}
+
+pub fn fmt_12(e: [f32; 12]) -> String {
+    format!("({},{},{}),({},{},{}),({},{},{}),({},{},{})",
+            e[0], e[1], e[2], e[3], e[4], e[5], e[6], e[7], e[8], e[9], e[10], e[11])
+}
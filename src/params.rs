@@ -0,0 +1,123 @@
+#![allow(dead_code)]
+
+use std;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+
+// Most contact points one colliding pair can make, max_contacts has to be at most this.
+pub const MAX_CONTACTS: usize = 64;
+
+// Everything about how the world steps that can change while it runs. Lockstep peers have to
+// change it on the same tick, so it goes out with the server's input for that tick.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct PhysicsParams {
+    pub gravity: f32, // Along y.
+    pub cfm: f32,
+    pub erp: f32,
+    pub iterations: u32, // QuickStep solver passes, more is stiffer and slower.
+    pub step_size: f32,
+    pub contact_surface_layer: f32, // Depth things may sink into each other without being pushed out.
+    #[serde(serialize_with = "serialize_limit", deserialize_with = "deserialize_limit")]
+    pub max_correcting_vel: f32, // Fastest contacts push things apart, infinite for no limit.
+    pub max_contacts: usize, // Per colliding pair.
+    pub auto_disable: bool, // Let bodies that have stopped go to sleep.
+    pub auto_disable_linear: f32, // Slower than these for long enough counts as stopped.
+    pub auto_disable_angular: f32,
+    pub auto_disable_steps: u32,
+    pub auto_disable_time: f32,
+}
+
+// JSON has no infinity, saves write no limit as null.
+fn serialize_limit<S: Serializer>(v: &f32, s: S) -> Result<S::Ok, S::Error> {
+    let limit = if v.is_infinite() { None } else { Some(*v) };
+    limit.serialize(s)
+}
+
+fn deserialize_limit<'de, D: Deserializer<'de>>(d: D) -> Result<f32, D::Error> {
+    Ok(try!(Option::<f32>::deserialize(d)).unwrap_or(std::f32::INFINITY))
+}
+
+// For the console, in the order `params` lists them.
+pub const NAMES: [&'static str; 13] = ["gravity", "cfm", "erp", "iterations", "step_size", "contact_surface_layer",
+                                       "max_correcting_vel", "max_contacts", "auto_disable", "auto_disable_linear",
+                                       "auto_disable_angular", "auto_disable_steps", "auto_disable_time"];
+
+impl PhysicsParams {
+    // Params that come off the wire go through this too, ODE does odd things with odd values.
+    pub fn check(&self) -> Result<(), String> {
+        if !self.gravity.is_finite() {
+            return Err(format!("gravity {} is not a number", self.gravity));
+        }
+        if !(self.cfm >= 0.0 && self.cfm.is_finite()) {
+            return Err(format!("cfm {} is negative", self.cfm));
+        }
+        if !(self.erp >= 0.0 && self.erp <= 1.0) {
+            return Err(format!("erp {} is not between 0 and 1", self.erp));
+        }
+        if self.iterations == 0 {
+            return Err("iterations has to be at least 1".to_string());
+        }
+        if !(self.step_size > 0.0 && self.step_size.is_finite()) {
+            return Err(format!("step size {} is not positive", self.step_size));
+        }
+        if !(self.contact_surface_layer >= 0.0 && self.contact_surface_layer.is_finite()) {
+            return Err(format!("contact surface layer {} is negative", self.contact_surface_layer));
+        }
+        if !(self.max_correcting_vel >= 0.0) {
+            return Err(format!("max correcting velocity {} is negative", self.max_correcting_vel));
+        }
+        if self.max_contacts < 1 || self.max_contacts > MAX_CONTACTS {
+            return Err(format!("max contacts {} is not between 1 and {}", self.max_contacts, MAX_CONTACTS));
+        }
+        for &(name, v) in [("auto disable linear", self.auto_disable_linear), ("auto disable angular", self.auto_disable_angular),
+                           ("auto disable time", self.auto_disable_time)].iter() {
+            if !(v >= 0.0 && v.is_finite()) {
+                return Err(format!("{} {} is negative", name, v));
+            }
+        }
+        return Ok(());
+    }
+
+    pub fn get(&self, name: &str) -> Option<String> {
+        Some(match name {
+            "gravity" => self.gravity.to_string(),
+            "cfm" => self.cfm.to_string(),
+            "erp" => self.erp.to_string(),
+            "iterations" => self.iterations.to_string(),
+            "step_size" => self.step_size.to_string(),
+            "contact_surface_layer" => self.contact_surface_layer.to_string(),
+            "max_correcting_vel" => self.max_correcting_vel.to_string(),
+            "max_contacts" => self.max_contacts.to_string(),
+            "auto_disable" => self.auto_disable.to_string(),
+            "auto_disable_linear" => self.auto_disable_linear.to_string(),
+            "auto_disable_angular" => self.auto_disable_angular.to_string(),
+            "auto_disable_steps" => self.auto_disable_steps.to_string(),
+            "auto_disable_time" => self.auto_disable_time.to_string(),
+            _ => return None,
+        })
+    }
+
+    // Parses value for the named param and checks the result, self is left alone on errors.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let mut p = *self;
+        let bad = |e: &std::fmt::Display| format!("bad value {} for {}: {}", value, name, e);
+        match name {
+            "gravity" => p.gravity = try!(value.parse().map_err(|e| bad(&e))),
+            "cfm" => p.cfm = try!(value.parse().map_err(|e| bad(&e))),
+            "erp" => p.erp = try!(value.parse().map_err(|e| bad(&e))),
+            "iterations" => p.iterations = try!(value.parse().map_err(|e| bad(&e))),
+            "step_size" => p.step_size = try!(value.parse().map_err(|e| bad(&e))),
+            "contact_surface_layer" => p.contact_surface_layer = try!(value.parse().map_err(|e| bad(&e))),
+            "max_correcting_vel" => p.max_correcting_vel = try!(value.parse().map_err(|e| bad(&e))),
+            "max_contacts" => p.max_contacts = try!(value.parse().map_err(|e| bad(&e))),
+            "auto_disable" => p.auto_disable = try!(value.parse().map_err(|e| bad(&e))),
+            "auto_disable_linear" => p.auto_disable_linear = try!(value.parse().map_err(|e| bad(&e))),
+            "auto_disable_angular" => p.auto_disable_angular = try!(value.parse().map_err(|e| bad(&e))),
+            "auto_disable_steps" => p.auto_disable_steps = try!(value.parse().map_err(|e| bad(&e))),
+            "auto_disable_time" => p.auto_disable_time = try!(value.parse().map_err(|e| bad(&e))),
+            _ => return Err(format!("no physics param called {}", name)),
+        }
+        try!(p.check());
+        *self = p;
+        return Ok(());
+    }
+}
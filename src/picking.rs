@@ -0,0 +1,83 @@
+#![allow(dead_code)]
+
+use vec::Vec3;
+use renderer::Renderer;
+use simulation::Simulation;
+use input::{self, Input, Grab};
+use query;
+
+// Nothing further than this from the camera can be picked.
+const MAX_PICK: f32 = 400.0;
+
+// Mouse picking for the viewers. Hovering lights a body up, holding the left button drags it
+// around at the distance it was grabbed, letting go throws it. What it does to the world comes
+// out of update() as an Input, so it goes wherever the keys go.
+pub struct Picker {
+    cursor: (i32, i32),
+    pressed: bool, // Since the last update.
+    released: bool,
+    held: Option<(usize, f32)>, // Body and its distance along the pick ray.
+    pub hovered: Option<usize>, // Held counts as hovered.
+}
+
+impl Picker {
+    pub fn new() -> Picker {
+        Picker { cursor: (0, 0), pressed: false, released: false, held: None, hovered: None }
+    }
+
+    pub fn moved(&mut self, x: i32, y: i32) {
+        self.cursor = (x, y);
+    }
+
+    pub fn button(&mut self, down: bool) {
+        if down {
+            self.pressed = true;
+        } else {
+            self.released = true;
+        }
+    }
+
+    pub fn holding(&self) -> bool {
+        self.held.is_some()
+    }
+
+    // Once a frame, after the window events.
+    pub fn update(&mut self, graphix: &Renderer, simulation: &Simulation) -> Input {
+        let (origin, dir) = graphix.unproject(self.cursor.0, self.cursor.1);
+        let hit = simulation.raycast(origin, dir, MAX_PICK, query::bodies_only);
+        self.hovered = hit.and_then(|h| h.body);
+        let mut result = Input::default();
+
+        if self.pressed && !self.released {
+            if let Some(body) = self.hovered {
+                // The spring pulls on the center, so keep that under the cursor.
+                let center = simulation.position(simulation.body_at(body).unwrap());
+                self.held = Some((body, (center - origin).dot(dir)));
+            }
+        }
+        if self.released && self.held.take().is_some() {
+            result.bits |= input::RELEASE;
+        }
+        self.pressed = false;
+        self.released = false;
+
+        if let Some((body, distance)) = self.held {
+            let net_id = match simulation.body_at(body) {
+                Some(id) => simulation.meta(id).unwrap().net_id(),
+                None => {
+                    self.held = None; // The world was replaced under us.
+                    return result;
+                }
+            };
+            let target = origin + Vec3::new(dir.x * distance, dir.y * distance, dir.z * distance);
+            result.grab = Some(Grab { body: net_id as usize, target: [target.x, target.y, target.z] });
+            self.hovered = Some(body);
+        }
+        return result;
+    }
+}
+
+// How a hovered body is drawn, half way to white.
+pub fn highlight(color: [f32; 3]) -> [f32; 3] {
+    [(color[0] + 1.0) / 2.0, (color[1] + 1.0) / 2.0, (color[2] + 1.0) / 2.0]
+}
@@ -0,0 +1,21 @@
+#![allow(dead_code)]
+
+use vec::Vec3;
+
+// What a ray ran into first. body is None for statics and the ground, like collision events.
+#[derive(Clone, Copy)]
+pub struct RayHit {
+    pub body: Option<usize>,
+    pub point: Vec3,
+    pub normal: Vec3, // Out of the surface, back towards the ray's origin.
+    pub distance: f32, // From the origin along the ray.
+}
+
+// Filters for the query functions, true keeps a candidate.
+pub fn everything(_: Option<usize>) -> bool {
+    true
+}
+
+pub fn bodies_only(body: Option<usize>) -> bool {
+    body.is_some()
+}
@@ -3,7 +3,6 @@
 extern crate gl;
 extern crate glutin;
 extern crate libc;
-extern crate ode;
 
 use shader_loader;
 
@@ -14,7 +13,10 @@ use std;
 // use glfw::{Context, WindowHint};
 use gl::types::*;
 use vec::Vec3;
-use ode::*;
+use config::RenderingConfig;
+use shape::Shape;
+use std::collections::HashMap;
+use simulation::{Pose, BodyId};
 
 const IDENT_MAT : [f32; 16] = [
     1.0, 0.0, 0.0, 0.0,
@@ -101,9 +103,9 @@ static CUBE_NORMAL_DATA : [f32; 108] = [
     0.0, 0.0, 1.0
 ];
 
-fn perspective(matrix : &mut [f32; 16], near : f32, far : f32, width : f32, height : f32) {
+fn perspective(matrix : &mut [f32; 16], fov : f32, near : f32, far : f32, width : f32, height : f32) {
     let ar = width/height;
-    let alpha = 95.0*consts::PI/180.0; // 95* FoV
+    let alpha = fov*consts::PI/180.0;
     let halftan = (alpha/2.0).tan();
     *matrix = [
         1.0/(ar*halftan), 0.0, 0.0, 0.0,
@@ -144,6 +146,124 @@ fn ltranslate(m: &mut [f32; 16], x: f32, y: f32, z: f32) {
     (*m)[11] += z;
 }
 
+// Scales along the model's own axes, before it is rotated and moved.
+fn rscale(m: &mut [f32; 16], x: f32, y: f32, z: f32) {
+    for i in 0..3 {
+        (*m)[i*4] *= x;
+        (*m)[i*4+1] *= y;
+        (*m)[i*4+2] *= z;
+    }
+}
+
+// The pose as a row major model matrix.
+fn pose_matrix(pose: &Pose) -> [f32; 16] {
+    let (pos, rot) = (pose.position, pose.rotation);
+    let mut m = IDENT_MAT;
+    for i in 0..3 {
+        m[i*4] = rot[i*4];
+        m[i*4+1] = rot[i*4+1];
+        m[i*4+2] = rot[i*4+2];
+    }
+    ltranslate(&mut m, pos[0], pos[1], pos[2]);
+    return m;
+}
+
+fn push_vertex(vertices: &mut Vec<f32>, normals: &mut Vec<f32>, v: Vec3, n: Vec3) {
+    vertices.extend_from_slice(&[v.x, v.y, v.z]);
+    normals.extend_from_slice(&[n.x, n.y, n.z]);
+}
+
+// Radius 1 around the origin.
+fn sphere_mesh() -> (Vec<f32>, Vec<f32>) {
+    let (stacks, slices) = (12, 16);
+    let point = |stack: u32, slice: u32| {
+        let theta = consts::PI * stack as f32 / stacks as f32;
+        let phi = 2.0 * consts::PI * slice as f32 / slices as f32;
+        Vec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos())
+    };
+    let (mut vertices, mut normals) = (Vec::new(), Vec::new());
+    for stack in 0..stacks {
+        for slice in 0..slices {
+            let quad = [point(stack, slice), point(stack+1, slice), point(stack+1, slice+1), point(stack, slice+1)];
+            for &i in [0, 1, 2, 0, 2, 3].iter() {
+                push_vertex(&mut vertices, &mut normals, quad[i], quad[i]); // On a unit sphere they are the same.
+            }
+        }
+    }
+    return (vertices, normals);
+}
+
+// Radius 1, length 1 along z, centered on the origin. Capsules use it for their middle.
+fn cylinder_mesh() -> (Vec<f32>, Vec<f32>) {
+    let slices = 16;
+    let (mut vertices, mut normals) = (Vec::new(), Vec::new());
+    let up = Vec3::new(0.0, 0.0, 1.0);
+    let down = Vec3::new(0.0, 0.0, -1.0);
+    for slice in 0..slices {
+        let a0 = 2.0 * consts::PI * slice as f32 / slices as f32;
+        let a1 = 2.0 * consts::PI * (slice + 1) as f32 / slices as f32;
+        let (n0, n1) = (Vec3::new(a0.cos(), a0.sin(), 0.0), Vec3::new(a1.cos(), a1.sin(), 0.0));
+        let quad = [Vec3::new(n0.x, n0.y, -0.5), Vec3::new(n1.x, n1.y, -0.5), Vec3::new(n1.x, n1.y, 0.5), Vec3::new(n0.x, n0.y, 0.5)];
+        let quad_normals = [n0, n1, n1, n0];
+        for &i in [0, 1, 2, 0, 2, 3].iter() {
+            push_vertex(&mut vertices, &mut normals, quad[i], quad_normals[i]);
+        }
+        for &v in [Vec3::new(0.0, 0.0, 0.5), quad[3], quad[2]].iter() {
+            push_vertex(&mut vertices, &mut normals, v, up);
+        }
+        for &v in [Vec3::new(0.0, 0.0, -0.5), quad[1], quad[0]].iter() {
+            push_vertex(&mut vertices, &mut normals, v, down);
+        }
+    }
+    return (vertices, normals);
+}
+
+// Flat shaded, every triangle gets its own normal.
+fn trimesh_mesh(vertices: &[[f32; 3]], triangles: &[[u32; 3]]) -> (Vec<f32>, Vec<f32>) {
+    let (mut out, mut normals) = (Vec::new(), Vec::new());
+    for t in triangles.iter() {
+        let v: Vec<Vec3> = t.iter().map(|&i| { let p = vertices[i as usize]; Vec3::new(p[0], p[1], p[2]) }).collect();
+        let n = (v[1] - v[0]).cross(v[2] - v[0]).normalize();
+        for p in v.iter() {
+            push_vertex(&mut out, &mut normals, *p, n);
+        }
+    }
+    return (out, normals);
+}
+
+// Vertex and normal buffers on the GPU, drawn as a list of triangles unless mode says otherwise.
+struct Mesh {
+    vertexbuffer: GLuint,
+    normalbuffer: GLuint,
+    count: GLsizei,
+    mode: GLenum,
+}
+
+impl Mesh {
+    unsafe fn upload(vertices: &[f32], normals: &[f32]) -> Mesh {
+        let mut mesh = Mesh { vertexbuffer: 0, normalbuffer: 0, count: (vertices.len() / 3) as GLsizei, mode: gl::TRIANGLES };
+        gl::GenBuffers(1, &mut mesh.vertexbuffer);
+        gl::BindBuffer(gl::ARRAY_BUFFER, mesh.vertexbuffer);
+        gl::BufferData(gl::ARRAY_BUFFER,
+                       (vertices.len() * mem::size_of::<f32>()) as isize,
+                       vertices.as_ptr() as *const _,
+                       gl::STATIC_DRAW);
+
+        gl::GenBuffers(1, &mut mesh.normalbuffer);
+        gl::BindBuffer(gl::ARRAY_BUFFER, mesh.normalbuffer);
+        gl::BufferData(gl::ARRAY_BUFFER,
+                       (normals.len() * mem::size_of::<f32>()) as isize,
+                       normals.as_ptr() as *const _,
+                       gl::STATIC_DRAW);
+        return mesh;
+    }
+
+    unsafe fn delete(&self) {
+        gl::DeleteBuffers(1, &self.vertexbuffer);
+        gl::DeleteBuffers(1, &self.normalbuffer);
+    }
+}
+
 
 
 
@@ -152,17 +272,19 @@ pub struct Renderer {
     pub window: glutin::Window,
     shaders: Vec<GLuint>,
     program_id: GLuint,
-    model_mat: [f32; 16],
     view_mat: [f32; 16],
     proj_mat: [f32; 16],
     vert_array_id: GLuint,
-    vertexbuffer: GLuint,
-    normalbuffer: GLuint,
+    cube: Mesh,
+    sphere: Mesh,
+    cylinder: Mesh,
+    trimeshes: HashMap<BodyId, Mesh>, // Built the first time the body is drawn, see forget_meshes.
+    clear_color: [f32; 3],
 }
 
 
 impl Renderer {
-    pub fn init(win_name: &str) -> Renderer{
+    pub fn init(win_name: &str, config: &RenderingConfig) -> Renderer{
         let mut ret;
         unsafe {
             //let mut glfw = glfw::init(glfw::FAIL_ON_ERRORS).unwrap();
@@ -190,23 +312,26 @@ impl Renderer {
                 window: window,
                 shaders: Vec::new(),
                 program_id: 0,
-                model_mat: IDENT_MAT,
                 view_mat: IDENT_MAT,
                 proj_mat: IDENT_MAT,
                 vert_array_id: 0,
-                vertexbuffer: 0,
-                normalbuffer: 0,
+                cube: Mesh { vertexbuffer: 0, normalbuffer: 0, count: 0 },
+                sphere: Mesh { vertexbuffer: 0, normalbuffer: 0, count: 0 },
+                cylinder: Mesh { vertexbuffer: 0, normalbuffer: 0, count: 0 },
+                trimeshes: HashMap::new(),
+                clear_color: config.clear_color,
             };
             ret.shaders.push(shader_loader::compile_shader_file("vertex.glsl", gl::VERTEX_SHADER));
             ret.shaders.push(shader_loader::compile_shader_file("frag.glsl", gl::FRAGMENT_SHADER));
             ret.program_id = shader_loader::link_program(ret.shaders[0], ret.shaders[1]);
             gl::UseProgram(ret.program_id);
 
-            perspective(&mut ret.proj_mat, 0.1, 400.0, 1.0, 1.0);
+            perspective(&mut ret.proj_mat, config.fov, 0.1, 400.0, 1.0, 1.0);
+            let (t, e, u) = (config.camera_target, config.camera_eye, config.camera_up);
             look_at(&mut ret.view_mat,
-                    Vec3::new(10.0, 0.0, 10.0),
-                    Vec3::new(10.0, 2.0, -10.0),
-                    Vec3::new(0.0, 1.0, 0.0)
+                    Vec3::new(t[0], t[1], t[2]),
+                    Vec3::new(e[0], e[1], e[2]),
+                    Vec3::new(u[0], u[1], u[2])
                     );
 
             let view_id = gl::GetUniformLocation(ret.program_id, CString::new("view").unwrap().as_ptr());
@@ -220,55 +345,146 @@ impl Renderer {
             gl::GenVertexArrays(1, &mut ret.vert_array_id);
             gl::BindVertexArray(ret.vert_array_id);
 
-            gl::GenBuffers(1, &mut ret.vertexbuffer);
-            gl::BindBuffer(gl::ARRAY_BUFFER, ret.vertexbuffer);
-            gl::BufferData(gl::ARRAY_BUFFER,
-                           mem::size_of_val(&CUBE_VERTEX_DATA) as isize,
-                           CUBE_VERTEX_DATA.as_ptr() as *const _,
-                           gl::STATIC_DRAW);
-
-            gl::GenBuffers(1, &mut ret.normalbuffer);
-            gl::BindBuffer(gl::ARRAY_BUFFER, ret.normalbuffer);
-            gl::BufferData(gl::ARRAY_BUFFER,
-                           mem::size_of_val(&CUBE_NORMAL_DATA) as isize,
-                           CUBE_NORMAL_DATA.as_ptr() as *const _,
-                           gl::STATIC_DRAW);
+            ret.cube = Mesh::upload(&CUBE_VERTEX_DATA, &CUBE_NORMAL_DATA);
+            let (vertices, normals) = sphere_mesh();
+            ret.sphere = Mesh::upload(&vertices, &normals);
+            let (vertices, normals) = cylinder_mesh();
+            ret.cylinder = Mesh::upload(&vertices, &normals);
         }
 
         return ret;
     }
 
+    pub fn clear(&self) {
+        unsafe {
+            gl::Enable(gl::DEPTH_TEST);
+            gl::DepthFunc(gl::LESS);
+            gl::ClearColor(self.clear_color[0], self.clear_color[1], self.clear_color[2], 1.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        }
+    }
+
     pub fn clean_up(&self) {
         unsafe {
             gl::DeleteProgram(self.program_id);
             gl::DeleteShader(self.shaders[0]);
             gl::DeleteShader(self.shaders[1]);
-            gl::DeleteBuffers(1, &self.vertexbuffer);
-            gl::DeleteBuffers(1, &self.normalbuffer);
+            self.cube.delete();
+            self.sphere.delete();
+            self.cylinder.delete();
+            for mesh in self.trimeshes.values() {
+                mesh.delete();
+            }
             gl::DeleteVertexArrays(1, &self.vert_array_id);
         }
     }
 
 
-    pub fn render_cube(&mut self, geom: dGeomID) {
-        unsafe {
-            let pos = std::slice::from_raw_parts(ode::dGeomGetPosition(geom), 3);
-            let rot = std::slice::from_raw_parts(ode::dGeomGetRotation(geom), 12); // 3 rows, 4 columns.
-            self.model_mat = IDENT_MAT;
-            for i in 0..3 {
-                self.model_mat[i*4] = rot[i*4];
-                self.model_mat[i*4+1] = rot[i*4+1];
-                self.model_mat[i*4+2] = rot[i*4+2];
+    // The ray under a window position, in pixels from the top left, as origin and direction.
+    // Undoes the projection for a direction in eye space, then turns that by the view.
+    pub fn unproject(&self, x: i32, y: i32) -> (Vec3, Vec3) {
+        let (width, height) = self.window.get_inner_size().unwrap_or((1, 1));
+        let ndc_x = 2.0 * x as f32 / width as f32 - 1.0;
+        let ndc_y = 1.0 - 2.0 * y as f32 / height as f32;
+        let eye_dir = Vec3::new(ndc_x / self.proj_mat[0], ndc_y / self.proj_mat[5], -1.0);
+        // View is rows s, up, l with a translation, its rotation transposed takes us back.
+        let v = &self.view_mat;
+        let (s, u, l) = (Vec3::new(v[0], v[1], v[2]), Vec3::new(v[4], v[5], v[6]), Vec3::new(v[8], v[9], v[10]));
+        let world = |p: Vec3| Vec3::new(s.x * p.x + u.x * p.y + l.x * p.z,
+                                        s.y * p.x + u.y * p.y + l.y * p.z,
+                                        s.z * p.x + u.z * p.y + l.z * p.z);
+        let eye = -world(Vec3::new(v[3], v[7], v[11]));
+        return (eye, world(eye_dir).normalize());
+    }
+
+    pub fn render_cube(&mut self, pose: &Pose, half_extents: [f32; 3], color: [f32; 3]) {
+        let mut model = pose_matrix(pose);
+        rscale(&mut model, half_extents[0] * 2.0, half_extents[1] * 2.0, half_extents[2] * 2.0); // The mesh is 1x1x1.
+        self.draw_mesh(&self.cube, model, color);
+    }
+
+    // Body ids start over with every simulation, call this when the world is replaced.
+    pub fn forget_meshes(&mut self) {
+        for (_, mesh) in self.trimeshes.drain() {
+            unsafe { mesh.delete(); }
+        }
+    }
+
+    // `body` is what it is cached by, a body's shape never changes.
+    pub fn render_shape(&mut self, body: BodyId, pose: &Pose, shape: &Shape, color: [f32; 3]) {
+        let mut model = pose_matrix(pose);
+        match *shape {
+            Shape::Box { size } => {
+                self.render_cube(pose, [size[0] / 2.0, size[1] / 2.0, size[2] / 2.0], color);
             }
-            ltranslate(&mut self.model_mat, pos[0], pos[1], pos[2]);
+            Shape::Sphere { radius } => {
+                rscale(&mut model, radius, radius, radius);
+                self.draw_mesh(&self.sphere, model, color);
+            }
+            Shape::Cylinder { radius, length } => {
+                rscale(&mut model, radius, radius, length);
+                self.draw_mesh(&self.cylinder, model, color);
+            }
+            Shape::Capsule { radius, length } => {
+                // A cylinder with a sphere on each end.
+                for &end in [-0.5, 0.5].iter() {
+                    let mut cap = model;
+                    rtranslate(&mut cap, 0.0, 0.0, end * length);
+                    rscale(&mut cap, radius, radius, radius);
+                    self.draw_mesh(&self.sphere, cap, color);
+                }
+                rscale(&mut model, radius, radius, length);
+                self.draw_mesh(&self.cylinder, model, color);
+            }
+            Shape::Trimesh { ref vertices, ref triangles } => {
+                if !self.trimeshes.contains_key(&body) {
+                    let (v, n) = trimesh_mesh(vertices, triangles);
+                    let mesh = unsafe { Mesh::upload(&v, &n) };
+                    self.trimeshes.insert(body, mesh);
+                }
+                self.draw_mesh(&self.trimeshes[&body], model, color);
+            }
+        }
+    }
+
+    // Lines in world space, for debug drawing like joints. They change every frame so the
+    // buffers are made and thrown away each call.
+    pub fn render_lines(&mut self, lines: &[([f32; 3], [f32; 3])], color: [f32; 3]) {
+        if lines.is_empty() {
+            return;
+        }
+        let mut vertices = Vec::new();
+        let mut normals = Vec::new();
+        for &(a, b) in lines.iter() {
+            for p in [a, b].iter() {
+                // Facing the light so the line gets the whole tint.
+                push_vertex(&mut vertices, &mut normals, Vec3::new(p[0], p[1], p[2]), Vec3::new(0.0, 0.7071, -0.7071));
+            }
+        }
+        let identity = [1.0, 0.0, 0.0, 0.0,
+                        0.0, 1.0, 0.0, 0.0,
+                        0.0, 0.0, 1.0, 0.0,
+                        0.0, 0.0, 0.0, 1.0];
+        unsafe {
+            let mut mesh = Mesh::upload(&vertices, &normals);
+            mesh.mode = gl::LINES;
+            self.draw_mesh(&mesh, identity, color);
+            mesh.delete();
+        }
+    }
+
+    fn draw_mesh(&self, mesh: &Mesh, model: [f32; 16], color: [f32; 3]) {
+        unsafe {
             let model_mat_id = gl::GetUniformLocation(self.program_id, CString::new("model").unwrap().as_ptr());
-            gl::UniformMatrix4fv(model_mat_id, 1, gl::TRUE, &self.model_mat[0]);
+            gl::UniformMatrix4fv(model_mat_id, 1, gl::TRUE, &model[0]);
+            let tint_id = gl::GetUniformLocation(self.program_id, CString::new("tint").unwrap().as_ptr());
+            gl::Uniform3f(tint_id, color[0], color[1], color[2]);
 
             let position_loc = gl::GetAttribLocation(self.program_id,
                                                      CString::new("ms_position").unwrap().as_ptr()) as GLuint;
             gl::EnableVertexAttribArray(position_loc); // Corresponds to location = X in vert_shader
             // Can be programatic using GetAttribLocation
-            gl::BindBuffer(gl::ARRAY_BUFFER, self.vertexbuffer);
+            gl::BindBuffer(gl::ARRAY_BUFFER, mesh.vertexbuffer);
             gl::VertexAttribPointer(
                 position_loc,
                 3,
@@ -280,7 +496,7 @@ impl Renderer {
             let normal_loc = gl::GetAttribLocation(self.program_id,
                                                    CString::new("ms_normal").unwrap().as_ptr()) as GLuint;
             gl::EnableVertexAttribArray(normal_loc); // Corresponds to location = X in vert_shader
-            gl::BindBuffer(gl::ARRAY_BUFFER, self.normalbuffer);
+            gl::BindBuffer(gl::ARRAY_BUFFER, mesh.normalbuffer);
             gl::VertexAttribPointer(
                 normal_loc,
                 3,
@@ -290,7 +506,7 @@ impl Renderer {
                 std::ptr::null()
                 );
 
-            gl::DrawArrays(gl::TRIANGLES, 0, 36);
+            gl::DrawArrays(mesh.mode, 0, mesh.count);
             gl::DisableVertexAttribArray(position_loc);
             gl::DisableVertexAttribArray(normal_loc);
         }
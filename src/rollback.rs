@@ -0,0 +1,183 @@
+#![allow(dead_code)]
+
+use std;
+use std::collections::BTreeMap;
+use config::ControlsConfig;
+use simulation::{Simulation, WorldState};
+use lockstep::{self, InputSync, REDUNDANCY};
+use input::{self, Input};
+use desync;
+
+// How far we will simulate past the last tick we have everyone's inputs for. Also the size of
+// the state ring, we can't rewind further than this.
+const MAX_ROLLBACK: u32 = 32;
+
+// GGPO style: never wait on remote inputs, guess them and simulate ahead. When the real input
+// turns out different from the guess we restore the state from before that tick and simulate
+// forward again with what we now know.
+pub struct Rollback {
+    pub tick: u32, // Next tick to simulate.
+    pub peer: usize,
+    peers: usize,
+    session: u8,
+    input_delay: u32,
+    confirmed: BTreeMap<u32, Vec<Option<Input>>>,
+    confirmed_to: u32, // First tick we are still missing someone's input for.
+    states: Vec<Option<(u32, WorldState)>>, // World before the tick ran, indexed by tick % MAX_ROLLBACK.
+    used: Vec<Option<(u32, Vec<Input>)>>, // Inputs that tick ran with, guesses included.
+    rollback_to: Option<u32>,
+    pending: Input,
+    next_local: u32,
+    sent: Vec<(u32, Input)>,
+    pub rollbacks: u64,
+    settled: Vec<(u32, WorldState)>,
+}
+
+impl Rollback {
+    pub fn new(peer: usize, peers: usize, session: u8, input_delay: u32) -> Rollback {
+        let mut rollback = Rollback {
+            tick: 0,
+            peer: peer,
+            peers: peers,
+            session: session,
+            input_delay: input_delay,
+            confirmed: BTreeMap::new(),
+            confirmed_to: 0,
+            states: vec![None; MAX_ROLLBACK as usize],
+            used: vec![None; MAX_ROLLBACK as usize],
+            rollback_to: None,
+            pending: Input::default(),
+            next_local: input_delay,
+            sent: Vec::new(),
+            rollbacks: 0,
+            settled: Vec::new(),
+        };
+        for t in 0..input_delay {
+            rollback.confirmed.insert(t, vec![Some(Input::default()); peers]);
+        }
+        return rollback;
+    }
+
+    fn set(&mut self, tick: u32, peer: usize, input: Input) {
+        let peers = self.peers;
+        self.confirmed.entry(tick).or_insert_with(|| vec![None; peers])[peer] = Some(input);
+    }
+
+    fn update_confirmed(&mut self, simulation: &Simulation) {
+        loop {
+            let complete = match self.confirmed.get(&self.confirmed_to) {
+                Some(inputs) => inputs.iter().all(|i| i.is_some()),
+                None => false,
+            };
+            if !complete || self.confirmed_to >= self.tick {
+                break;
+            }
+            self.confirmed.remove(&self.confirmed_to);
+            self.confirmed_to += 1;
+
+            // Every input before this tick is known, so the world at its start is final.
+            let t = self.confirmed_to;
+            if t % desync::INTERVAL == 0 {
+                if t == self.tick {
+                    self.settled.push((t, simulation.snapshot()));
+                } else if let Some((st, ref state)) = self.states[(t % MAX_ROLLBACK) as usize] {
+                    if st == t {
+                        self.settled.push((t, state.clone()));
+                    }
+                }
+            }
+        }
+    }
+
+    // Known inputs where we have them. Remote inputs are key presses rather than held keys,
+    // and a drag stays where the last grab put it, so the best guess for a missing one is
+    // that nothing happened.
+    fn inputs_for(&self, tick: u32) -> Vec<Input> {
+        match self.confirmed.get(&tick) {
+            Some(inputs) => inputs.iter().map(|i| i.unwrap_or_default()).collect(),
+            None => vec![Input::default(); self.peers],
+        }
+    }
+
+    fn simulate(&mut self, tick: u32, simulation: &mut Simulation, controls: &ControlsConfig) {
+        let slot = (tick % MAX_ROLLBACK) as usize;
+        self.states[slot] = Some((tick, simulation.snapshot()));
+        let inputs = self.inputs_for(tick);
+        for (peer, &input) in inputs.iter().enumerate() {
+            input::apply(simulation, peer, input, controls);
+        }
+        simulation.step();
+        self.used[slot] = Some((tick, inputs));
+    }
+}
+
+impl InputSync for Rollback {
+    fn add_local(&mut self, input: Input) {
+        self.pending.merge(input);
+    }
+
+    fn schedule_local(&mut self) -> Vec<u8> {
+        if self.next_local < self.tick + self.input_delay.max(1) {
+            let (tick, input, peer) = (self.next_local, self.pending, self.peer);
+            self.set(tick, peer, input);
+            self.sent.push((tick, input));
+            if self.sent.len() > REDUNDANCY {
+                self.sent.remove(0);
+            }
+            self.pending = Input::default();
+            self.next_local += 1;
+        }
+        return lockstep::encode_inputs(self.peer, self.session, &self.sent);
+    }
+
+    fn receive(&mut self, peer: usize, inputs: &[(u32, Input)]) {
+        if peer >= self.peers || peer == self.peer {
+            return;
+        }
+        // A peer can also be predicting up to MAX_ROLLBACK ticks past what it has confirmed.
+        let last = self.tick.saturating_add(lockstep::max_ahead(self.input_delay)).saturating_add(MAX_ROLLBACK);
+        for &(tick, input) in inputs.iter() {
+            if tick < self.confirmed_to || tick > last {
+                continue; // Repeat of something we already settled, or too far ahead to be real.
+            }
+            self.set(tick, peer, input);
+            if tick < self.tick {
+                let slot = (tick % MAX_ROLLBACK) as usize;
+                let guessed_wrong = match self.used[slot] {
+                    Some((t, ref used)) if t == tick => used[peer] != input,
+                    _ => true,
+                };
+                if guessed_wrong {
+                    self.rollback_to = Some(self.rollback_to.map_or(tick, |r| r.min(tick)));
+                }
+            }
+        }
+    }
+
+    fn advance(&mut self, simulation: &mut Simulation, controls: &ControlsConfig) {
+        if let Some(from) = self.rollback_to.take() {
+            let slot = (from % MAX_ROLLBACK) as usize;
+            match self.states[slot] {
+                Some((t, ref state)) if t == from => simulation.restore(state),
+                _ => panic!("Tick {} is too far back to roll back to from {}", from, self.tick),
+            }
+            for tick in from..self.tick {
+                self.simulate(tick, simulation, controls);
+            }
+            self.rollbacks += 1;
+        }
+
+        // Run every tick we have our own input for, as long as we stay inside the window we
+        // can still rewind. Past that we wait on the other peers like lockstep would.
+        while self.tick < self.next_local && self.tick < self.confirmed_to + MAX_ROLLBACK - 1 {
+            let tick = self.tick;
+            self.simulate(tick, simulation, controls);
+            self.tick += 1;
+        }
+        self.update_confirmed(simulation);
+    }
+
+    fn take_settled(&mut self) -> Vec<(u32, WorldState)> {
+        std::mem::replace(&mut self.settled, Vec::new())
+    }
+}
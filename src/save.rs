@@ -0,0 +1,59 @@
+#![allow(dead_code)]
+
+extern crate serde_json;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use vec::Vec3;
+use simulation::{Simulation, WorldState};
+use scene::{self, Scene};
+
+// Where the quick save key writes to and the quick load key reads from.
+pub const QUICKSAVE: &'static str = "quicksave.json";
+
+// A save is JSON holding the scene file the world was built from and the state of every body,
+// so loading it builds the scene again and then puts each body back where it was.
+#[derive(Serialize, Deserialize)]
+pub struct Save {
+    pub scene: String,
+    pub world: WorldState,
+}
+
+impl Save {
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        let json = try!(serde_json::to_string(self).map_err(|e| e.to_string()));
+        let mut file = try!(File::create(path).map_err(|e| e.to_string()));
+        return file.write_all(json.as_bytes()).map_err(|e| e.to_string());
+    }
+
+    pub fn read(path: &Path) -> Result<Save, String> {
+        let mut text = String::new();
+        try!(File::open(path).and_then(|mut f| f.read_to_string(&mut text)).map_err(|e| e.to_string()));
+        return serde_json::from_str(&text).map_err(|e| e.to_string());
+    }
+
+    // Builds the saved world into an empty simulation. Lockstep clients build what the server
+    // sends with this too, so nothing in the save is trusted.
+    pub fn build(&self, simulation: &mut Simulation) -> Result<(), String> {
+        let scene = try!(Scene::parse(&self.scene));
+        if self.world.bodies.len() > scene::MAX_BODIES {
+            return Err(format!("save has {} bodies, more than {}", self.world.bodies.len(), scene::MAX_BODIES));
+        }
+        try!(self.world.check());
+        if self.world.bodies.len() < scene.body_count() {
+            return Err(format!("save has {} bodies but its scene makes {}", self.world.bodies.len(), scene.body_count()));
+        }
+        scene.build(simulation);
+        // Anything added after the scene was built, restore puts the right mass on it. Saves
+        // from before shapes were kept only have cubes.
+        for i in simulation.body_count()..self.world.bodies.len() {
+            let body = &self.world.bodies[i];
+            let shape = self.world.shapes.get(i).cloned().unwrap_or_default();
+            try!(shape.check().map_err(|e| format!("body {}: {}", i, e)));
+            simulation.create_body(shape, body.params.mass, Vec3::new(body.pos[0], body.pos[1], body.pos[2]));
+        }
+        simulation.restore(&self.world);
+        return Ok(());
+    }
+}
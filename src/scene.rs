@@ -0,0 +1,319 @@
+#![allow(dead_code)]
+
+extern crate serde_json;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use vec::Vec3;
+use simulation::{Simulation, StaticId};
+use entity;
+use shape::Shape;
+use joint::JointKind;
+use material::Material;
+use std::collections::HashMap;
+
+// Most bodies a scene can make, repeats included. Far more than we can step in a frame, it is
+// there so a typo in a repeat count is an error instead of an hour building bodies. Only an
+// overflow guard, a world this big is far too big to send: the server checks what a client
+// would get fits in a datagram before running it.
+pub const MAX_BODIES: usize = 100000;
+
+// Scene files are JSON:
+//   {
+//     "player": index of the body inputs act on, default 0
+//     "materials": { name: { "friction": mu, "restitution": r, "soft_erp": erp, "soft_cfm": cfm,
+//                            "rolling_friction": rho }, ... }
+//     "ground_material": name for the y = 0 plane,
+//     "bodies": [{
+//       "shape": { "type": "box", "size": [x, y, z] }
+//                or { "type": "sphere", "radius": r }
+//                or { "type": "capsule" | "cylinder", "radius": r, "length": l }  along z
+//                or { "type": "trimesh", "vertices": [[x, y, z], ...], "triangles": [[i, j, k], ...] },
+//       "mass": kg, "position": [x, y, z], "rotation": [w, x, y, z],
+//       "velocity": [x, y, z], "angular_velocity": [x, y, z],
+//       "material": name, "color": [r, g, b], "name": for the console, copies share it,
+//       "repeat": { "count": [x, y, z], "spacing": [x, y, z] }  makes a grid of copies
+//     }],
+//     "statics": [{ "type": "box", "size": [x, y, z], "position": [...], "rotation": [...], "material": name }
+//                 { "type": "plane", "normal": [x, y, z], "offset": d, "material": name }],
+//     "joints": [{ "type": "ball" | "hinge" | "slider" | "fixed", "body1": index,
+//                  "body2": index or absent for the world, "anchor": [...], "axis": [...],
+//                  "limits": [low, high], "motor": { "velocity": v, "max_force": f },
+//                  "break_force": newtons }]
+//   }
+// Everything but the shape type has a default, anything without a material is rough and dead
+// (see Material::default). Bodies are created in file order, a repeat
+// runs x outermost and z innermost, and lockstep peers rely on that order matching.
+
+// The player cube at (0,1,0) and a 10x10 grid of small light cubes above the ground, what the
+// server runs when not given --scene.
+pub const DEFAULT: &'static str = include_str!("../scenes/default.json");
+
+pub const DEFAULT_COLOR: [f32; 3] = entity::DEFAULT_COLOR;
+pub const JOINT_COLOR: [f32; 3] = [1.0, 0.8, 0.2]; // Lines from bodies to their joints.
+pub const SPARK_COLOR: [f32; 3] = [1.0, 1.0, 1.0]; // Collision effects on clients.
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Repeat {
+    pub count: [u32; 3],
+    pub spacing: [f32; 3],
+}
+
+impl Default for Repeat {
+    fn default() -> Repeat {
+        Repeat { count: [1, 1, 1], spacing: [1.0, 1.0, 1.0] }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BodyDesc {
+    pub shape: Shape,
+    pub mass: f32,
+    pub position: [f32; 3],
+    pub rotation: [f32; 4], // w, x, y, z
+    pub velocity: [f32; 3],
+    pub angular_velocity: [f32; 3],
+    pub material: Option<String>, // A name from the scene's materials.
+    pub color: Option<[f32; 3]>,
+    pub name: Option<String>,
+    pub repeat: Option<Repeat>,
+}
+
+impl Default for BodyDesc {
+    fn default() -> BodyDesc {
+        BodyDesc {
+            shape: Shape::default(),
+            mass: 1.0,
+            position: [0.0, 0.0, 0.0],
+            rotation: [1.0, 0.0, 0.0, 0.0],
+            velocity: [0.0, 0.0, 0.0],
+            angular_velocity: [0.0, 0.0, 0.0],
+            material: None,
+            color: None,
+            name: None,
+            repeat: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StaticDesc {
+    Box {
+        #[serde(default = "unit_size")]
+        size: [f32; 3],
+        position: [f32; 3],
+        #[serde(default = "identity")]
+        rotation: [f32; 4],
+        #[serde(default)]
+        material: Option<String>,
+    },
+    Plane {
+        normal: [f32; 3],
+        #[serde(default)]
+        offset: f32,
+        #[serde(default)]
+        material: Option<String>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JointDesc {
+    #[serde(rename = "type")]
+    pub kind: JointKind,
+    pub body1: usize,
+    #[serde(default)]
+    pub body2: Option<usize>, // None attaches to the world.
+    #[serde(default)]
+    pub anchor: [f32; 3],
+    #[serde(default = "up")]
+    pub axis: [f32; 3],
+    #[serde(default)]
+    pub limits: Option<[f32; 2]>, // Low and high stop.
+    #[serde(default)]
+    pub motor: Option<Motor>,
+    #[serde(default)]
+    pub break_force: Option<f32>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Motor {
+    pub velocity: f32,
+    pub max_force: f32,
+}
+
+fn unit_size() -> [f32; 3] { [1.0, 1.0, 1.0] }
+fn identity() -> [f32; 4] { [1.0, 0.0, 0.0, 0.0] }
+fn up() -> [f32; 3] { [0.0, 1.0, 0.0] }
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scene {
+    pub player: usize,
+    pub materials: HashMap<String, Material>,
+    pub ground_material: Option<String>,
+    pub bodies: Vec<BodyDesc>,
+    pub statics: Vec<StaticDesc>,
+    pub joints: Vec<JointDesc>,
+}
+
+fn v3(a: [f32; 3]) -> Vec3 {
+    Vec3::new(a[0], a[1], a[2])
+}
+
+// The file as text, that is what gets sent to clients so they can build the same thing.
+pub fn read(path: &Path) -> String {
+    let mut text = String::new();
+    File::open(path).and_then(|mut f| f.read_to_string(&mut text))
+        .unwrap_or_else(|e| panic!("Could not read scene {}: {}", path.display(), e));
+    return text;
+}
+
+impl Scene {
+    pub fn parse(text: &str) -> Result<Scene, String> {
+        let scene: Scene = try!(serde_json::from_str(text).map_err(|e| e.to_string()));
+        for (name, material) in scene.materials.iter() {
+            try!(material.check().map_err(|e| format!("material {}: {}", name, e)));
+        }
+        try!(scene.material(&scene.ground_material).map(|_| ()));
+        for s in scene.statics.iter() {
+            match *s {
+                StaticDesc::Box { size, ref material, .. } => {
+                    try!(Shape::Box { size: size }.check());
+                    try!(scene.material(material));
+                }
+                StaticDesc::Plane { ref material, .. } => {
+                    try!(scene.material(material));
+                }
+            }
+        }
+        let count = match scene.checked_body_count() {
+            Some(count) if count <= MAX_BODIES => count,
+            _ => return Err(format!("scene makes more than {} bodies", MAX_BODIES)),
+        };
+        if count > 0 && scene.player >= count {
+            return Err(format!("player is body {} but the scene only has {}", scene.player, count));
+        }
+        for body in scene.bodies.iter() {
+            if !(body.mass > 0.0) {
+                return Err(format!("body mass {} is not positive", body.mass));
+            }
+            try!(body.shape.check());
+            try!(scene.material(&body.material));
+        }
+        for joint in scene.joints.iter() {
+            if joint.body1 >= count || joint.body2.map_or(false, |b| b >= count) {
+                return Err(format!("joint between bodies {} and {:?} but the scene only has {}", joint.body1, joint.body2, count));
+            }
+            // dJointAttach and the axis setters assert on these.
+            if joint.body2 == Some(joint.body1) {
+                return Err(format!("joint joins body {} to itself", joint.body1));
+            }
+            if joint.axis.iter().any(|a| !a.is_finite()) || joint.axis.iter().all(|&a| a == 0.0) {
+                return Err(format!("joint axis {:?} has no direction", joint.axis));
+            }
+        }
+        return Ok(scene);
+    }
+
+    // The material a desc names, the default for None.
+    fn material(&self, name: &Option<String>) -> Result<Material, String> {
+        match *name {
+            Some(ref name) => self.materials.get(name).cloned().ok_or_else(|| format!("no material called {}", name)),
+            None => Ok(Material::default()),
+        }
+    }
+
+    // Repeats included. Only for scenes from parse, which checked it fits.
+    pub fn body_count(&self) -> usize {
+        self.checked_body_count().expect("scene makes more bodies than fit in a usize")
+    }
+
+    // None when the repeat counts overflow.
+    fn checked_body_count(&self) -> Option<usize> {
+        let mut total = 0usize;
+        for b in self.bodies.iter() {
+            let copies = match b.repeat {
+                Some(ref r) => r.count.iter().fold(Some(1usize), |n, &c| n.and_then(|n| n.checked_mul(c as usize))),
+                None => Some(1),
+            };
+            total = match copies.and_then(|c| total.checked_add(c)) {
+                Some(total) => total,
+                None => return None,
+            };
+        }
+        return Some(total);
+    }
+
+    // Creates everything in the simulation, bodies get their names and colors as metadata.
+    pub fn build(&self, simulation: &mut Simulation) {
+        let mut bodies = Vec::new();
+        for body in self.bodies.iter() {
+            let repeat = match body.repeat {
+                Some(ref r) => (r.count, r.spacing),
+                None => ([1, 1, 1], [0.0, 0.0, 0.0]),
+            };
+            let (count, spacing) = repeat;
+            for x in 0..count[0] {
+                for y in 0..count[1] {
+                    for z in 0..count[2] {
+                        let pos = Vec3::new(body.position[0] + x as f32 * spacing[0],
+                                            body.position[1] + y as f32 * spacing[1],
+                                            body.position[2] + z as f32 * spacing[2]);
+                        let i = simulation.create_body(body.shape.clone(), body.mass, pos);
+                        simulation.set_rotation(i, body.rotation);
+                        simulation.set_velocity(i, v3(body.velocity), v3(body.angular_velocity));
+                        if body.material.is_some() {
+                            simulation.set_material(i, self.material(&body.material).unwrap());
+                        }
+                        {
+                            let meta = simulation.meta_mut(i).unwrap();
+                            meta.name = body.name.clone();
+                            meta.color = body.color.unwrap_or(DEFAULT_COLOR);
+                        }
+                        bodies.push(i);
+                    }
+                }
+            }
+        }
+        // Parse already found every material name, so the unwraps are safe.
+        if self.ground_material.is_some() {
+            simulation.set_static_material(StaticId::Ground, self.material(&self.ground_material).unwrap());
+        }
+        for s in self.statics.iter() {
+            let (id, material) = match *s {
+                StaticDesc::Box { size, position, rotation, ref material } => {
+                    let half = Vec3::new(size[0] / 2.0, size[1] / 2.0, size[2] / 2.0);
+                    (simulation.create_static_cube(half, v3(position), rotation), material)
+                }
+                StaticDesc::Plane { normal, offset, ref material } => {
+                    (simulation.create_static_plane(v3(normal), offset), material)
+                }
+            };
+            if material.is_some() {
+                simulation.set_static_material(id, self.material(material).unwrap());
+            }
+        }
+        for j in self.joints.iter() {
+            let joint = simulation.create_joint(j.kind, bodies[j.body1], j.body2.map(|b| bodies[b]));
+            simulation.set_joint_anchor(joint, v3(j.anchor));
+            simulation.set_joint_axis(joint, v3(j.axis));
+            if let Some(limits) = j.limits {
+                simulation.set_joint_limits(joint, limits[0], limits[1]);
+            }
+            if let Some(ref motor) = j.motor {
+                simulation.set_joint_motor(joint, motor.velocity, motor.max_force);
+            }
+            simulation.set_joint_break_force(joint, j.break_force);
+        }
+        simulation.player = self.player;
+        if let Some(&player) = bodies.get(self.player) {
+            simulation.meta_mut(player).unwrap().flags |= entity::PLAYER;
+        }
+    }
+}
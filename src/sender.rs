@@ -1,13 +1,457 @@
-use std::net::UdpSocket;
+extern crate rust_network;
+
+use rust_network::{packet, capture, net, input, lockstep};
+use std::io;
+use std::net::{UdpSocket, SocketAddr};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use packet::{ByteCount, Snapshot, CubePose};
+use capture::{CaptureReader, Direction};
+use input::{Input, Grab};
+use lockstep::Message;
+
+fn usage() -> ! {
+    println!("Usage: sender listen <port> [--connect <host[:port]>]");
+    println!("       sender read <capture file>");
+    println!("       sender fuzz server <host[:port]> [--count <n>] [--seed <n>] [--capture <file>]");
+    println!("       sender fuzz client <port> [--count <n>] [--seed <n>] [--capture <file>]");
+    std::process::exit(1);
+}
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 2 {
+        usage();
+    }
+    let mut inspector = Inspector { packets: 0, totals: Default::default() };
+    match args[0].as_str() {
+        "listen" => {
+            let port = args[1].parse().unwrap_or_else(|_| usage());
+            let connect = match args.get(2).map(|s| s.as_str()) {
+                Some("--connect") => Some(args.get(3).unwrap_or_else(|| usage()).clone()),
+                Some(_) => usage(),
+                None => None,
+            };
+            listen(&mut inspector, port, connect);
+        }
+        "read" => read(&mut inspector, Path::new(&args[1])),
+        "fuzz" => {
+            if args.len() < 3 {
+                usage();
+            }
+            let target = match args[1].as_str() {
+                "server" => Target::Server(args[2].clone()),
+                "client" => Target::Client(args[2].parse().unwrap_or_else(|_| usage())),
+                _ => usage(),
+            };
+            let mut options = FuzzOptions { count: 1000, seed: 0x2545F4914F6CDD1D, capture: None };
+            let mut rest = args[3..].iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--count" => options.count = rest.next().and_then(|s| s.parse().ok()).unwrap_or_else(|| usage()),
+                    "--seed" => options.seed = rest.next().and_then(|s| s.parse().ok()).unwrap_or_else(|| usage()),
+                    "--capture" => options.capture = Some(rest.next().unwrap_or_else(|| usage()).clone()),
+                    _ => usage(),
+                }
+            }
+            fuzz(target, options);
+        }
+        _ => usage(),
+    }
+}
+
+// Binds the port and prints whatever arrives there. With --connect we say hello to a server
+// first so it starts streaming snapshots to us, the same way a client would.
+fn listen(inspector: &mut Inspector, port: u16, connect: Option<String>) {
     print!("Starting sender . . . ");
-    let socket = UdpSocket::bind("127.0.0.1:35555").unwrap();
+    let socket = net::bind_dual_stack(port).unwrap();
     print!("Done.\n");
 
-    let mut buf = [0; 100];
-    println!("Sending data.");
-    socket.send_to(&mut buf, "127.0.0.1:34555");
+    if let Some(host) = connect {
+        let hello = [0; 9000];
+        let server = net::resolve(&host, 35555).unwrap()[0];
+        println!("Saying hello to {}.", server);
+        socket.send_to(&hello, server).unwrap();
+    }
+
+    let mut buf = [0; 9000];
+    let start = std::time::Instant::now();
+    loop {
+        let (amt, from) = socket.recv_from(&mut buf).unwrap();
+        let elapsed = start.elapsed();
+        let time_us = elapsed.as_secs() * 1000000 + (elapsed.subsec_nanos() / 1000) as u64;
+        inspector.inspect(time_us, "received from", net::canonical(from), &buf[..amt]);
+        if inspector.packets % 100 == 0 {
+            inspector.print_totals();
+        }
+    }
+}
+
+fn read(inspector: &mut Inspector, path: &Path) {
+    let reader = CaptureReader::open(path);
+    println!("Capture recorded by the {:?}.", reader.role);
+    for record in reader {
+        let direction = match record.direction { Direction::Sent => "sent to", Direction::Received => "received from" };
+        inspector.inspect(record.time_us, direction, record.peer, &record.data);
+    }
+    inspector.print_totals();
+}
+
+struct Inspector {
+    packets: usize,
+    totals: ByteCount,
+}
+
+impl Inspector {
+    fn inspect(&mut self, time_us: u64, direction: &str, peer: SocketAddr, data: &[u8]) {
+        self.packets += 1;
+        println!("#{} +{}.{:06}s {} {}, {} bytes",
+                 self.packets, time_us / 1000000, time_us % 1000000, direction, peer, data.len());
+        match packet::decode(data) {
+            Ok(snapshot) => {
+                print_snapshot(&snapshot);
+                self.totals.add(&snapshot.bytes);
+            }
+            Err(e) => println!("  undecodable: {}", e),
+        }
+    }
+
+    fn print_totals(&self) {
+        println!("Totals over {} packets:", self.packets);
+        print_bytes(&self.totals);
+    }
+}
+
+fn print_snapshot(snapshot: &Snapshot) {
+    println!("  header: paused={} init={}", snapshot.paused, snapshot.init);
+    if snapshot.paused && !snapshot.init {
+        println!("  paused, no geoms follow");
+    } else {
+        println!("  geoms: {}", snapshot.cubes.len());
+    }
+    for (i, cube) in snapshot.cubes.iter().enumerate() {
+        match *cube {
+            None => println!("  cube {}: at rest", i),
+            Some(ref c) => {
+                print!("  cube {}: moving pos={} rot={}", i, packet::fmt_3(c.pos), packet::fmt_12(c.rot));
+                if let Some(mass) = c.mass {
+                    print!(" mass={}", mass);
+                }
+                print!("\n");
+            }
+        }
+    }
+    for &(tag, ref payload) in snapshot.extensions.iter() {
+        println!("  extension {}: {} bytes", tag, payload.len());
+        if tag == packet::EXT_SHAPES {
+            match packet::decode_shapes(payload) {
+                Ok(shapes) => {
+                    for (i, s) in shapes.iter().enumerate() {
+                        println!("    shape {}: {:?}", i, s);
+                    }
+                }
+                Err(e) => println!("    bad shapes: {}", e),
+            }
+        }
+        if tag == packet::EXT_COLLISIONS {
+            match packet::decode_collisions(payload) {
+                Ok(events) => {
+                    for e in events.iter() {
+                        println!("    {:?} {} and {:?} at {}, impulse {}", e.phase, e.body1, e.body2, packet::fmt_3(e.point), e.impulse);
+                    }
+                }
+                Err(e) => println!("    bad collisions: {}", e),
+            }
+        }
+        if tag == packet::EXT_JOINTS {
+            match packet::decode_joint_lines(payload) {
+                Ok(lines) => {
+                    for l in lines.iter() {
+                        println!("    joint {} to {:?} at {}", l.body1, l.body2, packet::fmt_3(l.anchor));
+                    }
+                }
+                Err(e) => println!("    bad joints: {}", e),
+            }
+        }
+        if tag == packet::EXT_PARAMS {
+            match packet::decode_params(payload) {
+                Ok(params) => println!("    {:?}", params),
+                Err(e) => println!("    bad params: {}", e),
+            }
+        }
+    }
+    print_bytes(&snapshot.bytes);
+}
+
+fn print_bytes(bytes: &ByteCount) {
+    let total = bytes.total();
+    let fields = [
+        ("header", bytes.header),
+        ("geom count", bytes.geom_count),
+        ("moving flags", bytes.moving_flags),
+        ("position", bytes.position),
+        ("rotation", bytes.rotation),
+        ("mass", bytes.mass),
+        ("extensions", bytes.extensions),
+    ];
+    print!("  bytes:");
+    for &(name, count) in fields.iter() {
+        let percent = if total > 0 { count as f32 * 100.0 / total as f32 } else { 0.0 };
+        print!(" {} {} ({:.1}%),", name, count, percent);
+    }
+    print!(" total {}\n", total);
+}
+
+enum Target {
+    Server(String), // We connect to it like a client would and send it inputs, all it reads after the hello.
+    Client(u16), // We stand in for the server on this port and wait for a client to say hello.
+}
+
+// Where the fields some mutations aim at are in the packets we mutate.
+struct Layout {
+    count_at: usize, // How many entries follow, the rest of the packet is those.
+    count_len: usize,
+    entries_at: usize,
+}
+
+const SNAPSHOT_LAYOUT: Layout = Layout { count_at: 2, count_len: 4, entries_at: 6 };
+const INPUTS_LAYOUT: Layout = Layout { count_at: 3, count_len: 1, entries_at: 4 };
+
+struct FuzzOptions {
+    count: usize,
+    seed: u64,
+    capture: Option<String>, // Real packets to mutate, otherwise we make up our own.
+}
+
+// xorshift64*, good enough to pick mutations and reproducible from --seed.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        return self.0.wrapping_mul(0x2545F4914F6CDD1D);
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        if n == 0 { 0 } else { (self.next() % n as u64) as usize }
+    }
+}
+
+#[derive(Debug)]
+enum Health {
+    Alive,
+    Silent, // The server stopped sending to us, snapshots or its inputs.
+    Crashed, // Port unreachable, nobody is listening any more.
+}
+
+// `socket` is connected to the target, so a closed port comes back as ECONNREFUSED,
+// which is how we notice the target went away. A server should also still be streaming.
+fn probe(socket: &UdpSocket, expect_snapshots: bool) -> Health {
+    let mut buf = [0; 9000];
+    socket.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+    if !expect_snapshots {
+        // Clients never answer, so send them something harmless to provoke the ICMP error.
+        if let Err(e) = socket.send(&packet::encode(true, false, &[], &[])) {
+            if e.kind() == io::ErrorKind::ConnectionRefused { return Health::Crashed; }
+        }
+    }
+    let mut got_snapshot = false;
+    // Drain the backlog, but a server streaming faster than we read would keep us here forever.
+    let draining = Instant::now();
+    while draining.elapsed() < Duration::from_millis(500) {
+        match socket.recv(&mut buf) {
+            Ok(_) => {
+                got_snapshot = true;
+                socket.set_read_timeout(Some(Duration::from_millis(1))).unwrap();
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => return Health::Crashed,
+            Err(_) => break,
+        }
+    }
+    if expect_snapshots && !got_snapshot {
+        return Health::Silent;
+    }
+    return Health::Alive;
+}
+
+fn made_up_snapshot(rng: &mut Rng) -> Vec<u8> {
+    let init = rng.below(4) == 0;
+    let mut cubes = Vec::new();
+    for _ in 0..rng.below(120) {
+        if !init && rng.below(2) == 0 {
+            cubes.push(None);
+            continue;
+        }
+        let f = |rng: &mut Rng| (rng.below(2000) as f32 - 1000.0) / 100.0;
+        let pos = [f(rng), f(rng), f(rng)];
+        let rot = [1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        cubes.push(Some(CubePose { pos: pos, rot: rot, mass: Some(0.1) }));
+    }
+    return packet::encode(false, init, &cubes, &[]);
+}
+
+// What a client sends in every mode, peer 1 in the first session.
+fn made_up_inputs(rng: &mut Rng) -> Vec<u8> {
+    let mut inputs = Vec::new();
+    let tick = rng.below(1000) as u32;
+    for t in 0..1 + rng.below(lockstep::REDUNDANCY) {
+        let mut input = Input { bits: rng.below(256) as u8, ..Default::default() };
+        if rng.below(2) == 0 {
+            let f = |rng: &mut Rng| (rng.below(2000) as f32 - 1000.0) / 100.0;
+            input.grab = Some(Grab { body: rng.below(200), target: [f(rng), f(rng), f(rng)] });
+        }
+        if rng.below(8) == 0 {
+            input.time_scale = Some(rng.below(16) as f32 / 2.0); // Ignored from peer 1, it still gets decoded.
+        }
+        if rng.below(8) == 0 {
+            input.run = Some(rng.below(100) as u32);
+        }
+        inputs.push((tick + t as u32, input));
+    }
+    return lockstep::encode_inputs(1, 0, &inputs);
+}
+
+// Picks one of the attacks and applies it to a known good packet. There is no sequence
+// number in the protocol yet, so "replay" is just an older packet sent again.
+fn mutate(rng: &mut Rng, layout: &Layout, good: &[Vec<u8>], sent: &[Vec<u8>]) -> (&'static str, Vec<u8>) {
+    let mut packet = good[rng.below(good.len())].clone();
+    match rng.below(6) {
+        0 => {
+            let len = rng.below(packet.len().min(layout.entries_at) + 1);
+            packet.truncate(len);
+            ("truncated header", packet)
+        }
+        1 => {
+            let len = 1 + rng.below(packet.len());
+            packet.truncate(len);
+            ("truncated body", packet)
+        }
+        2 => {
+            if packet.len() >= layout.entries_at {
+                let count = [0xffffffffu32, 0x7fffffff, 65536, packet.len() as u32][rng.below(4)];
+                for b in 0..layout.count_len {
+                    packet[layout.count_at + b] = (count >> (8 * b)) as u8;
+                }
+            }
+            ("huge count", packet)
+        }
+        3 => {
+            let specials = [std::f32::NAN, std::f32::INFINITY, std::f32::NEG_INFINITY, 0.0, -1.0, 1e38];
+            let value = specials[rng.below(specials.len())];
+            if packet.len() >= layout.entries_at + 4 {
+                // Not always aligned to a real float, which is its own kind of garbage.
+                let at = layout.entries_at + rng.below(packet.len() - layout.entries_at - 3);
+                let bits = value.to_bits();
+                for b in 0..4 {
+                    packet[at + b] = (bits >> (8 * b)) as u8;
+                }
+            }
+            ("bad float", packet)
+        }
+        4 => {
+            if sent.is_empty() {
+                ("replayed packet", packet)
+            } else {
+                ("replayed packet", sent[rng.below(sent.len())].clone())
+            }
+        }
+        _ => {
+            let flips = 1 + rng.below(8);
+            for _ in 0..flips {
+                let at = rng.below(packet.len());
+                if at < packet.len() {
+                    packet[at] ^= 1 << rng.below(8);
+                }
+            }
+            ("bit flips", packet)
+        }
+    }
+}
+
+fn fuzz(target: Target, options: FuzzOptions) {
+    let mut rng = Rng(options.seed | 1);
+
+    // Servers get what clients send them, clients what servers do.
+    let to_server = match target {
+        Target::Server(_) => true,
+        Target::Client(_) => false,
+    };
+    let layout = if to_server { &INPUTS_LAYOUT } else { &SNAPSHOT_LAYOUT };
+    let mut good = Vec::new();
+    if let Some(ref path) = options.capture {
+        for record in CaptureReader::open(Path::new(path)) {
+            let ok = if to_server {
+                match lockstep::decode(&record.data) {
+                    Some(Message::Inputs(..)) => true,
+                    _ => false,
+                }
+            } else {
+                packet::decode(&record.data).is_ok()
+            };
+            if ok {
+                good.push(record.data);
+            }
+        }
+        println!("Loaded {} good packets from {}.", good.len(), path);
+    }
+    while good.len() < 16 {
+        good.push(if to_server { made_up_inputs(&mut rng) } else { made_up_snapshot(&mut rng) });
+    }
+
+    let hello = [0; 9000];
+    let mut buf = [0; 9000];
+    let (socket, peer, expect_snapshots) = match target {
+        Target::Server(ref host) => {
+            let (socket, server, _) = net::connect(host, 35555, 0, &hello).unwrap();
+            (socket, server, true)
+        }
+        Target::Client(port) => {
+            let socket = net::bind_dual_stack(port).unwrap();
+            println!("Waiting on client.");
+            let (_, client) = socket.recv_from(&mut buf).unwrap();
+            (socket, client, false)
+        }
+    };
+    socket.connect(peer).unwrap();
+    if let Target::Client(_) = target {
+        socket.send(&good[0]).unwrap(); // Client is blocked waiting on its first snapshot.
+    }
+
+    println!("Fuzzing {} with {} packets, seed {}.", net::canonical(peer), options.count, options.seed);
+    let mut sent: Vec<Vec<u8>> = Vec::new();
+    for n in 0..options.count {
+        let (kind, packet) = if rng.below(10) == 0 {
+            ("spoofed source port", good[rng.below(good.len())].clone())
+        } else {
+            mutate(&mut rng, layout, &good, &sent)
+        };
+        // Spoofing a port needs raw sockets, the best we can do is come from ports it has not
+        // seen. A client should drop these without looking at them.
+        let result = if kind == "spoofed source port" {
+            net::bind_for(&peer, 0).and_then(|s| s.send_to(&packet, peer))
+        } else {
+            socket.send(&packet)
+        };
+        if let Err(e) = result {
+            println!("#{} {} ({} bytes) failed to send: {}", n, kind, packet.len(), e);
+        }
+        if sent.len() < 64 {
+            sent.push(packet.clone());
+        }
 
-    drop(socket);
+        if n % 50 == 49 || n + 1 == options.count {
+            let health = probe(&socket, expect_snapshots);
+            match health {
+                Health::Alive => (),
+                _ => {
+                    println!("Target {:?} after packet #{}, the last one was a {} of {} bytes:", health, n, kind, packet.len());
+                    println!("  {:?}", packet);
+                    return;
+                }
+            }
+        }
+    }
+    println!("Target survived {} packets.", options.count);
 }
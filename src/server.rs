@@ -1,21 +1,39 @@
 //extern crate glfw;
 extern crate gl;
 extern crate glutin;
-extern crate libc;
-extern crate ode;
 extern crate byteorder;
 extern crate time;
+extern crate rust_network;
 
 mod shader_loader;
 mod renderer;
-mod simulation;
-mod vec;
+mod picking;
+mod console;
 
-use std::net::UdpSocket;
-use vec::Vec3;
+use rust_network::{vec, net, config, params, clock, capture, packet, input, scene, shape, collision, query,
+                   lockstep, rollback, desync, save, simulation};
 use renderer::Renderer;
 use simulation::Simulation;
+use scene::Scene;
+use save::Save;
+use console::{Console, Command};
+use clock::{MIN_TIME_SCALE, MAX_TIME_SCALE};
+use picking::Picker;
+use input::Input;
+use config::{Config, NetMode};
+use lockstep::{Lockstep, Message, InputSync};
+use rollback::Rollback;
+use desync::{Checksum, Detector};
+use capture::{Recorder, Role, Direction};
+use std::path::Path;
+use net::NetThread;
 use time::{Duration, PreciseTime};
+use byteorder::{LittleEndian, WriteBytesExt};
+
+// Snapshots can get lost, so a params change rides along on this many of them.
+const PARAMS_REPEAT: u32 = 30;
+// Frames between sends of a lockstep restart, it holds the whole world so not every frame.
+const RESTART_RESEND: u32 = 30;
 
 //static VERTEX_DATA : [f32; 9] = [
     //-1.0, -1.0, -1.0,
@@ -39,92 +57,494 @@ fn format_bytes(bytes: u64) -> String {
 
 fn main() {
     print!("Starting server . . . ");
-    let socket = UdpSocket::bind("127.0.0.1:35555").unwrap();
-    let mut buf = [0; 9000];
+    let (config, rest) = Config::from_args(std::env::args().skip(1));
+    let mut scene_text = scene::DEFAULT.to_string();
+    let mut load_path = None;
+    let mut args = rest.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scene" => scene_text = scene::read(Path::new(&args.next().expect("--scene needs a scene file"))),
+            "--load" => load_path = Some(args.next().expect("--load needs a save file")),
+            _ => println!("Ignoring unknown argument {}", arg),
+        }
+    }
+    let net_io = NetThread::spawn(net::bind_dual_stack(config.network.server_port).unwrap());
+    let mut recorder = config.network.record.as_ref().map(|p| Recorder::create(Path::new(p), Role::Server));
+    let console = Console::spawn();
 
     //Init everything
-    let mut graphix = Renderer::init("Server Window");
-    let mut simulation = Simulation::init();
-
-    simulation.create_cube(10.0, Vec3::new(0.0, 1.0, 0.0));
-    for n in 0..100 {
-        simulation.create_cube(0.1, Vec3::new(((n/10)*2) as f32, 3.0, ((n%10)*2) as f32));
+    let mut graphix = Renderer::init("Server Window", &config.rendering);
+    let mut simulation = Simulation::init(&config.physics);
+    match load_path {
+        Some(ref path) => {
+            let save = Save::read(Path::new(path)).unwrap_or_else(|e| panic!("Could not read save {}: {}", path, e));
+            scene_text = save.scene.clone();
+            save.build(&mut simulation).unwrap_or_else(|e| panic!("Could not load save {}: {}", path, e));
+        }
+        None => Scene::parse(&scene_text).unwrap_or_else(|e| panic!("Bad scene: {}", e)).build(&mut simulation),
     }
+    check_sendable(&config, &scene_text, load_path.is_some(), &simulation).unwrap_or_else(|e| panic!("{}", e));
 
     println!("Waiting on client.");
-    let (_, client) = socket.recv_from(&mut buf).unwrap(); //Receive into the buffer
-    println!("Client connected from {}.", client);
+    match config.network.mode {
+        NetMode::Snapshot => run_snapshot(&config, &mut scene_text, &console, &net_io, &mut recorder, &mut graphix, &mut simulation),
+        NetMode::Lockstep | NetMode::Rollback => run_lockstep(&config, &mut scene_text, load_path.is_some(), &console, &net_io, &mut recorder, &mut graphix, &mut simulation),
+    }
 
-    // Do Simulation and rendering
-    println!("Beginning simulation");
-    let mut bytes_sent = socket.send_to(&simulation.serialize(true), client).unwrap() as u64; // Init packet to prevent blank client
+    //Do clean ups
+    graphix.clean_up();
+}
+
+// We simulate and stream the poses of everything that moved to the client.
+fn run_snapshot(config: &Config, scene_text: &mut String, console: &Console, net_io: &NetThread,
+                recorder: &mut Option<Recorder>, graphix: &mut Renderer, simulation: &mut Simulation) {
+    let mut client = None;
+    let mut tick = 0u32; // Steps actually taken, pauses don't count.
+    let mut bytes_sent = 0u64;
     let mut last_second = PreciseTime::now();
+    let mut hits = Vec::new(); // Replicated collisions since the last snapshot.
+    let mut params = simulation.params(); // What the client was last told.
+    let mut params_repeat = 0;
+    let mut picker = Picker::new();
+    let mut remote = Input::default(); // What the client asked for since the last frame.
     let mut should_close = false;
     while !should_close {
-        bytes_sent += socket.send_to(&simulation.serialize(false), client).unwrap() as u64;
-        let now = PreciseTime::now();
-        let differential = last_second.to(now);
-        if differential > Duration::seconds(1) {
-            println!("Sent {} in {} second.", format_bytes(bytes_sent), differential);
-            bytes_sent = 0;
-            last_second = now;
+        while let Some((hello, from)) = net_io.poll() {
+            if let Some(c) = client {
+                // Only the one client for now, after its hello it only sends inputs.
+                if net::same_peer(c, from) {
+                    if let Some(ref mut r) = *recorder {
+                        r.record(Direction::Received, from, &hello);
+                    }
+                    if let Some(Message::Inputs(_, _, inputs)) = lockstep::decode(&hello) {
+                        for (_, input) in inputs {
+                            remote.merge(input);
+                        }
+                    }
+                }
+                continue;
+            }
+            if let Some(ref mut r) = *recorder {
+                r.record(Direction::Received, from, &hello);
+            }
+            println!("Client connected from {}.", net::canonical(from));
+            println!("Beginning simulation");
+            let init = init_packet(scene_text, simulation); // Init packet to prevent blank client
+            if let Some(ref mut r) = *recorder {
+                r.record(Direction::Sent, from, &init);
+            }
+            bytes_sent += init.len() as u64;
+            net_io.send(init, from);
+            client = Some(from);
         }
 
-        unsafe { // Opengl calls are unsafe
-            simulation.step();
+        // Until someone joins we keep the window alive but hold the world still.
+        if let Some(client) = client {
+            let mut snapshot = simulation.serialize(false);
+            let mut tick_bytes = vec![];
+            tick_bytes.write_u32::<LittleEndian>(tick).unwrap();
+            packet::push_extension(&mut snapshot, packet::EXT_TICK, &tick_bytes);
+            if !simulation.is_paused() && tick % desync::INTERVAL == 0 {
+                let checksum = Checksum::of(tick, &simulation.snapshot());
+                packet::push_extension(&mut snapshot, packet::EXT_CHECKSUM, &checksum.encode());
+            }
+            if !hits.is_empty() {
+                packet::push_extension(&mut snapshot, packet::EXT_COLLISIONS, &packet::encode_collisions(&hits));
+                hits.clear();
+            }
+            let joints = simulation.joint_lines();
+            if !joints.is_empty() {
+                packet::push_extension(&mut snapshot, packet::EXT_JOINTS, &packet::encode_joint_lines(&joints));
+            }
+            if simulation.params() != params {
+                params = simulation.params();
+                params_repeat = PARAMS_REPEAT;
+            }
+            if params_repeat > 0 {
+                packet::push_extension(&mut snapshot, packet::EXT_PARAMS, &packet::encode_params(&params));
+                params_repeat -= 1;
+            }
+            // The client has its own clock, it would drift from ours in slow motion. Tell it
+            // how far to go instead.
+            let steps = simulation.advance_clock();
+            let mut steps_bytes = vec![];
+            steps_bytes.write_u32::<LittleEndian>(steps).unwrap();
+            packet::push_extension(&mut snapshot, packet::EXT_STEPS, &steps_bytes);
+            if let Some(ref mut r) = *recorder {
+                r.record(Direction::Sent, client, &snapshot);
+            }
+            bytes_sent += snapshot.len() as u64;
+            net_io.send(snapshot, client);
+            let now = PreciseTime::now();
+            let differential = last_second.to(now);
+            if differential > Duration::seconds(1) {
+                println!("Sent {} in {} second.", format_bytes(bytes_sent), differential);
+                bytes_sent = 0;
+                last_second = now;
+            }
 
-            gl::Enable(gl::DEPTH_TEST);
-            gl::DepthFunc(gl::LESS);
-            gl::ClearColor(0.38, 0.906, 0.722, 1.0);
-            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+            simulation.step_by(steps);
+            tick += steps;
+            hits.extend(simulation.collision_events().iter().filter(|e| collision::replicated(e)).cloned());
+        }
 
-            for geom_data in simulation.geoms.iter() {
-                graphix.render_cube(geom_data.0); // Seperate from ODE?
+        draw(graphix, simulation, picker.hovered);
+
+        let mut bits = 0;
+        let mut commands = Vec::new();
+        let time_scale = simulation.time_scale();
+        for event in graphix.window.poll_events() {
+            bits |= handle_window_event(event, &mut should_close, &mut commands, &mut picker, time_scale);
+        }
+        let mut local = picker.update(graphix, simulation);
+        local.bits |= bits;
+        input::apply(simulation, 0, local, &config.controls);
+        input::apply(simulation, 1, remote, &config.controls);
+        remote = Input::default();
+
+        commands.extend(console.poll());
+        for command in commands {
+            if run_command(command, config, scene_text, simulation) {
+                graphix.forget_meshes();
+                // The client is still showing the old world, start it over like it just joined.
+                if let Some(client) = client {
+                    let init = init_packet(scene_text, simulation);
+                    if let Some(ref mut r) = *recorder {
+                        r.record(Direction::Sent, client, &init);
+                    }
+                    bytes_sent += init.len() as u64;
+                    net_io.send(init, client);
+                }
+            }
+        }
+
+        graphix.window.swap_buffers().unwrap();
+    }
+}
+
+// Everyone runs the same scene on the same inputs, we only relay inputs. We are peer 0 and
+// the client peer 1. Rollback mode runs this too, only the InputSync differs.
+fn run_lockstep(config: &Config, scene_text: &mut String, mut loaded: bool, console: &Console, net_io: &NetThread,
+                recorder: &mut Option<Recorder>, graphix: &mut Renderer, simulation: &mut Simulation) {
+    let mut client = None;
+    let rollback = config.network.mode == NetMode::Rollback;
+    let mut session = 0;
+    let mut sync = new_sync(rollback, session, config.network.input_delay);
+    let mut restart = None; // Start for a loaded world and frames since, until the client's inputs say it has it.
+    let mut detector = Detector::new();
+    let mut picker = Picker::new();
+    let mut should_close = false;
+    while !should_close {
+        while let Some((packet, from)) = net_io.poll() {
+            if let Some(ref mut r) = *recorder {
+                r.record(Direction::Received, from, &packet);
+            }
+            match client {
+                None => {
+                    println!("Client connected from {}.", net::canonical(from));
+                    println!("Beginning simulation");
+                    let start = start_message(config, session, scene_text, loaded, simulation);
+                    if let Some(ref mut r) = *recorder {
+                        r.record(Direction::Sent, from, &start);
+                    }
+                    net_io.send(start, from);
+                    client = Some(from);
+                }
+                Some(c) if net::same_peer(c, from) => {
+                    match lockstep::decode(&packet) {
+                        Some(Message::Inputs(peer, s, inputs)) => if s == session {
+                            restart = None;
+                            sync.receive(peer, &inputs);
+                        },
+                        Some(Message::Start(_)) => (),
+                        // Until it has restarted its checksums are for the old world.
+                        None => if restart.is_none() {
+                            if let Some(checksum) = Checksum::decode_message(&packet) {
+                                detector.add_remote(checksum);
+                            }
+                        },
+                    }
+                }
+                Some(_) => (),
             }
         }
 
+        let mut bits = 0;
+        let mut commands = Vec::new();
+        let time_scale = simulation.time_scale();
         for event in graphix.window.poll_events() {
-            handle_window_event(event, &mut simulation, &mut should_close)
+            bits |= handle_window_event(event, &mut should_close, &mut commands, &mut picker, time_scale);
+        }
+        let mut local = picker.update(graphix, simulation);
+        local.bits |= bits;
+        commands.extend(console.poll());
+        for command in commands {
+            // Once a client is in, time controls have to reach it for the same tick, like params.
+            let command = match client {
+                Some(_) => match time_control(command, &mut local) {
+                    Some(command) => command,
+                    None => continue,
+                },
+                None => command,
+            };
+            match command {
+                // Both sides have to change on the same tick, so it goes out as our input.
+                Command::Set(name, value) if client.is_some() => {
+                    let mut params = local.params.unwrap_or(simulation.params());
+                    match params.set(&name, &value) {
+                        Ok(()) => local.params = Some(params),
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                command => if run_command(command, config, scene_text, simulation) {
+                    loaded = true;
+                    graphix.forget_meshes();
+                    if client.is_some() {
+                        // Both sides start over from the loaded world at tick 0, like a new join.
+                        session = session.wrapping_add(1);
+                        sync = new_sync(rollback, session, config.network.input_delay);
+                        detector = Detector::new();
+                        local = Input::default(); // Picked in the old world.
+                        restart = Some((start_message(config, session, scene_text, loaded, simulation), 0));
+                    }
+                },
+            }
         }
 
+        if let (Some(client), Some(&mut (ref start, ref mut frames))) = (client, restart.as_mut()) {
+            if *frames % RESTART_RESEND == 0 {
+                if let Some(ref mut r) = *recorder {
+                    r.record(Direction::Sent, client, start);
+                }
+                net_io.send(start.clone(), client);
+            }
+            *frames += 1;
+        }
+
+        if let Some(client) = client {
+            sync.add_local(local);
+            let packet = sync.schedule_local();
+            if let Some(ref mut r) = *recorder {
+                r.record(Direction::Sent, client, &packet);
+            }
+            net_io.send(packet, client);
+            sync.advance(simulation, &config.controls);
+            for (tick, state) in sync.take_settled() {
+                let checksum = Checksum::of(tick, &state);
+                let message = checksum.encode_message();
+                if let Some(ref mut r) = *recorder {
+                    r.record(Direction::Sent, client, &message);
+                }
+                net_io.send(message, client);
+                detector.add_local(checksum, state);
+            }
+        }
+
+        draw(graphix, simulation, picker.hovered);
         graphix.window.swap_buffers().unwrap();
     }
+}
 
-    //Do clean ups
-    graphix.clean_up();
-    simulation.clean_up();
+fn new_sync(rollback: bool, session: u8, input_delay: u32) -> Box<InputSync> {
+    if rollback {
+        Box::new(Rollback::new(0, 2, session, input_delay))
+    } else {
+        Box::new(Lockstep::new(0, 2, session, input_delay))
+    }
+}
+
+// The client is peer 1. Nothing has moved before anyone joins, so the world only differs from
+// the scene after a load.
+fn start_message(config: &Config, session: u8, scene_text: &str, loaded: bool, simulation: &Simulation) -> Vec<u8> {
+    lockstep::encode_start(&lockstep::Start {
+        rollback: config.network.mode == NetMode::Rollback,
+        peer: 1,
+        peers: 2,
+        session: session,
+        input_delay: config.network.input_delay,
+        params: simulation.params(),
+        controls: config.controls.clone(),
+        scene: scene_text.to_string(),
+        state: if loaded { Some(simulation.save_state()) } else { None },
+    })
+}
+
+// What a joining client gets goes out as one datagram, the init packet in snapshot mode and the
+// Start in lockstep, a world that doesn't fit can't be sent. Each init packet extension is at
+// most u16::MAX long too. Checked when a world is built, not when the client shows up.
+fn check_sendable(config: &Config, scene_text: &str, loaded: bool, simulation: &Simulation) -> Result<(), String> {
+    let joining = if config.network.mode == NetMode::Snapshot {
+        if scene_text.len() > std::u16::MAX as usize {
+            return Err(format!("Scene file is {} bytes, too big to send to clients", scene_text.len()));
+        }
+        let shapes = packet::encode_shapes(&simulation.shapes());
+        if shapes.len() > std::u16::MAX as usize {
+            return Err(format!("Shapes are {} bytes, too big to send to clients, trimeshes too detailed?", shapes.len()));
+        }
+        init_packet(scene_text, simulation)
+    } else {
+        start_message(config, 0, scene_text, loaded, simulation)
+    };
+    if joining.len() > net::MAX_PAYLOAD {
+        return Err(format!("The world is {} bytes, too big to send to clients, more than {} fit in a datagram",
+                           joining.len(), net::MAX_PAYLOAD));
+    }
+    return Ok(());
+}
+
+// Snapshot of every body plus the scene and shapes, so the client can build statics and
+// give bodies their colors too.
+fn init_packet(scene_text: &str, simulation: &Simulation) -> Vec<u8> {
+    let mut init = simulation.serialize(true);
+    packet::push_extension(&mut init, packet::EXT_SCENE, scene_text.as_bytes());
+    let shapes = packet::encode_shapes(&simulation.shapes());
+    packet::push_extension(&mut init, packet::EXT_SHAPES, &shapes);
+    packet::push_extension(&mut init, packet::EXT_PARAMS, &packet::encode_params(&simulation.params()));
+    return init;
+}
+
+// True when the world was replaced.
+fn run_command(command: Command, config: &Config, scene_text: &mut String, simulation: &mut Simulation) -> bool {
+    match command {
+        Command::Save(path) => {
+            let save = Save { scene: scene_text.clone(), world: simulation.save_state() };
+            match save.write(Path::new(&path)) {
+                Ok(()) => println!("Saved the world to {}", path),
+                Err(e) => println!("Could not save to {}: {}", path, e),
+            }
+            return false;
+        }
+        Command::Load(path) => {
+            let save = match Save::read(Path::new(&path)) {
+                Ok(save) => save,
+                Err(e) => {
+                    println!("Could not read save {}: {}", path, e);
+                    return false;
+                }
+            };
+            // Built on the side so a bad save leaves the running world alone.
+            let mut loaded = Simulation::init(&config.physics);
+            match save.build(&mut loaded).and_then(|()| check_sendable(config, &save.scene, true, &loaded)) {
+                Ok(()) => {
+                    *simulation = loaded; // The old world is dropped here.
+                    *scene_text = save.scene;
+                    println!("Loaded {}", path);
+                    return true;
+                }
+                Err(e) => {
+                    println!("Could not load save {}: {}", path, e);
+                    return false;
+                }
+            }
+        }
+        Command::Set(name, value) => {
+            let mut params = simulation.params();
+            match params.set(&name, &value).and_then(|_| simulation.set_params(params)) {
+                Ok(()) => println!("{} is now {}", name, params.get(&name).unwrap()),
+                Err(e) => println!("{}", e),
+            }
+            return false;
+        }
+        Command::Params => {
+            let params = simulation.params();
+            for name in params::NAMES.iter() {
+                println!("  {} = {}", name, params.get(name).unwrap());
+            }
+            return false;
+        }
+        Command::Step => {
+            simulation.single_step();
+            return false;
+        }
+        Command::Run(steps) => {
+            simulation.run_for(steps);
+            return false;
+        }
+        Command::Speed(scale) => {
+            match simulation.set_time_scale(scale) {
+                Ok(()) => println!("Time scale {}", scale),
+                Err(e) => println!("{}", e),
+            }
+            return false;
+        }
+    }
+}
+
+// Puts a time control into our lockstep input, hands anything else back.
+fn time_control(command: Command, local: &mut Input) -> Option<Command> {
+    match command {
+        Command::Step => local.bits |= input::STEP,
+        Command::Run(steps) => local.run = Some(steps),
+        Command::Speed(scale) => {
+            println!("Time scale {}", scale);
+            local.time_scale = Some(scale);
+        }
+        command => return Some(command),
+    }
+    return None;
 }
 
-fn handle_window_event(event: glutin::Event, simulation: &mut Simulation, should_close: &mut bool ) {
+fn draw(graphix: &mut Renderer, simulation: &Simulation, hovered: Option<usize>) {
+    graphix.clear();
+    for (id, meta) in simulation.bodies() {
+        let color = if hovered == Some(id.index()) { picking::highlight(meta.color) } else { meta.color };
+        graphix.render_shape(id, &simulation.pose(id), simulation.shape(id).unwrap(), color);
+    }
+    for &(ref pose, half_extents) in simulation.static_boxes().iter() {
+        graphix.render_cube(pose, half_extents, scene::DEFAULT_COLOR);
+    }
+    graphix.render_lines(&simulation.joint_segments(&simulation.joint_lines()), scene::JOINT_COLOR);
+}
+
+// Returns the input bits for whatever the event asks the player to do, F5 and F9 quick save
+// and load like the console commands, [ and ] halve and double the time scale and \ puts it
+// back. The mouse goes to the picker.
+fn handle_window_event(event: glutin::Event, should_close: &mut bool, commands: &mut Vec<Command>, picker: &mut Picker,
+                       time_scale: f32) -> u8 {
     use glutin::Event;
     use glutin::ElementState as KeyState;
     use glutin::VirtualKeyCode as Key;
 
-    let push_force = 500f32;
     match event {
         Event::KeyboardInput(KeyState::Pressed, _, Some(Key::Q)) => {
             *should_close = true;
+            0
+        }
+        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::F5)) => {
+            commands.push(Command::Save(save::QUICKSAVE.to_string()));
+            0
         }
-        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::P)) => {
-            simulation.toggle_pause();
+        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::F9)) => {
+            commands.push(Command::Load(save::QUICKSAVE.to_string()));
+            0
         }
-        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::Space)) => {
-            let lev_force = 250f32;
-            let pos = simulation.get_location(simulation.geoms[0].0);
-            simulation.apply_force(simulation.geoms[0].0, Vec3::new(0.0, (lev_force-(pos.z * 5.0).powf(3.0)).min(0.0), 0.0));
+        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::LBracket)) => {
+            commands.push(Command::Speed((time_scale / 2.0).max(MIN_TIME_SCALE)));
+            0
         }
-        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::Up)) => {
-            simulation.apply_force(simulation.geoms[0].0, Vec3::new(0.0, 0.0, push_force));
+        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::RBracket)) => {
+            commands.push(Command::Speed((time_scale * 2.0).min(MAX_TIME_SCALE)));
+            0
         }
-        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::Down)) => {
-            simulation.apply_force(simulation.geoms[0].0, Vec3::new(0.0, 0.0, -push_force));
+        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::Backslash)) => {
+            commands.push(Command::Speed(1.0));
+            0
         }
-        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::Left)) => {
-            simulation.apply_force(simulation.geoms[0].0, Vec3::new(push_force, 0.0, 0.0));
+        Event::MouseMoved(x, y) => {
+            picker.moved(x, y);
+            0
         }
-        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::Right)) => {
-            simulation.apply_force(simulation.geoms[0].0, Vec3::new(-push_force, 0.0, 0.0));
+        Event::MouseInput(state, glutin::MouseButton::Left) => {
+            picker.button(state == KeyState::Pressed);
+            0
         }
-        _ => ()
+        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::P)) => input::PAUSE,
+        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::Period)) => input::STEP,
+        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::Space)) => input::LEVITATE,
+        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::Up)) => input::UP,
+        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::Down)) => input::DOWN,
+        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::Left)) => input::LEFT,
+        Event::KeyboardInput(KeyState::Pressed, _, Some(Key::Right)) => input::RIGHT,
+        _ => 0
     }
 }
@@ -0,0 +1,111 @@
+#![allow(dead_code)]
+
+use std::collections::HashSet;
+
+// What a body collides and renders as. Capsules and cylinders run along the body's z axis,
+// like ODE builds them, and `length` doesn't count a capsule's end caps.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Shape {
+    Box { size: [f32; 3] },
+    Sphere { radius: f32 },
+    Capsule { radius: f32, length: f32 },
+    Cylinder { radius: f32, length: f32 },
+    Trimesh { vertices: Vec<[f32; 3]>, triangles: Vec<[u32; 3]> },
+}
+
+impl Default for Shape {
+    fn default() -> Shape {
+        Shape::Box { size: [1.0, 1.0, 1.0] }
+    }
+}
+
+fn positive(name: &str, v: f32) -> Result<(), String> {
+    if v > 0.0 && v.is_finite() {
+        Ok(())
+    } else {
+        Err(format!("{} {} is not a positive number", name, v))
+    }
+}
+
+// An edge that isn't matched by the same edge running the other way in exactly one other
+// triangle, None when the triangles close up into a consistently wound surface.
+fn open_edge(triangles: &[[u32; 3]]) -> Option<(u32, u32)> {
+    let mut edges = HashSet::new();
+    for t in triangles.iter() {
+        for i in 0..3 {
+            if !edges.insert((t[i], t[(i + 1) % 3])) {
+                return Some((t[i], t[(i + 1) % 3])); // Two triangles the same way round.
+            }
+        }
+    }
+    return edges.iter().find(|&&(a, b)| !edges.contains(&(b, a))).cloned();
+}
+
+// Sum of the tetrahedra each triangle makes with the origin, positive when the triangles run
+// counterclockwise seen from outside like ODE wants.
+fn signed_volume(vertices: &[[f32; 3]], triangles: &[[u32; 3]]) -> f64 {
+    let mut volume = 0.0;
+    for t in triangles.iter() {
+        let a = vertices[t[0] as usize];
+        let b = vertices[t[1] as usize];
+        let c = vertices[t[2] as usize];
+        let cross = [b[1] as f64 * c[2] as f64 - b[2] as f64 * c[1] as f64,
+                     b[2] as f64 * c[0] as f64 - b[0] as f64 * c[2] as f64,
+                     b[0] as f64 * c[1] as f64 - b[1] as f64 * c[0] as f64];
+        volume += a[0] as f64 * cross[0] + a[1] as f64 * cross[1] + a[2] as f64 * cross[2];
+    }
+    return volume / 6.0;
+}
+
+impl Shape {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Shape::Box { .. } => "box",
+            Shape::Sphere { .. } => "sphere",
+            Shape::Capsule { .. } => "capsule",
+            Shape::Cylinder { .. } => "cylinder",
+            Shape::Trimesh { .. } => "trimesh",
+        }
+    }
+
+    // ODE asserts (or worse) on anything this turns down, so shapes from files and the
+    // network go through here before they reach it.
+    pub fn check(&self) -> Result<(), String> {
+        match *self {
+            Shape::Box { size } => {
+                for s in size.iter() {
+                    try!(positive("box size", *s));
+                }
+            }
+            Shape::Sphere { radius } => try!(positive("sphere radius", radius)),
+            Shape::Capsule { radius, length } | Shape::Cylinder { radius, length } => {
+                try!(positive("radius", radius));
+                try!(positive("length", length));
+            }
+            Shape::Trimesh { ref vertices, ref triangles } => {
+                if triangles.is_empty() {
+                    return Err("trimesh has no triangles".to_string());
+                }
+                if vertices.iter().any(|v| v.iter().any(|c| !c.is_finite())) {
+                    return Err("trimesh has a vertex that isn't a number".to_string());
+                }
+                if let Some(t) = triangles.iter().find(|t| t.iter().any(|&i| i as usize >= vertices.len())) {
+                    return Err(format!("trimesh triangle {:?} is past the last of {} vertices", t, vertices.len()));
+                }
+                // The mass ODE works out only means something for a closed surface, an open one
+                // (a single triangle) gets a mass that depends on where the origin is.
+                if let Some(edge) = open_edge(triangles) {
+                    return Err(format!("trimesh edge {:?} isn't shared the other way round by another triangle, it has to be closed", edge));
+                }
+                // dMassSetTrimeshTotal divides by this, a flat mesh makes a NaN mass that
+                // dBodySetMass asserts on. Negative is inside out.
+                let volume = signed_volume(vertices, triangles);
+                if !(volume > 0.0) || !volume.is_finite() {
+                    return Err(format!("trimesh volume {} is not positive, is it flat or wound clockwise seen from outside?", volume));
+                }
+            }
+        }
+        return Ok(());
+    }
+}
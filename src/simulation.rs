@@ -7,121 +7,1227 @@ extern crate byteorder;
 use std;
 use ode::*;
 use vec::Vec3;
+use packet;
+use shape::Shape;
+use joint::{JointKind, JointId, JointLine};
+use material::{self, Material};
+use collision::{self, CollisionEvent, PairKey, Touch};
+use std::collections::BTreeMap;
+use query::RayHit;
+use config::PhysicsConfig;
+use params::{PhysicsParams, MAX_CONTACTS};
+use clock::{self, Clock};
+use ode_handle::{Library, World, Space, JointGroup, Body, Geom, TriMeshData};
+use entity::{self, Entities, EntityId, EntityMeta};
 
-use std::io::Cursor;
-use byteorder::{LittleEndian, WriteBytesExt, ReadBytesExt};
+use byteorder::{LittleEndian, WriteBytesExt};
 
 
 
+// What step() hands near_callback through dSpaceCollide.
+struct Collide {
+    world: dWorldID,
+    contact_group: dJointGroupID,
+    max_contacts: usize,
+    contacts: Vec<Contact>, // Every contact joint made, for collision events.
+}
+
+struct Contact {
+    geom1: dGeomID,
+    geom2: dGeomID,
+    point: [f32; 3],
+    normal: [f32; 3], // From geom2 into geom1.
+    feedback: Box<dJointFeedback>, // Filled in by the step, boxed so it stays put until then.
+}
+
 extern fn near_callback(data : *mut libc::c_void, obj1 : ode::dGeomID, obj2 : ode::dGeomID) {
     unsafe {
-        let data: &mut (dWorldID, dJointGroupID) = std::mem::transmute(data);
+        let data: &mut Collide = std::mem::transmute(data);
         let b1 = dGeomGetBody(obj1);
         let b2 = dGeomGetBody(obj2);
-        let mut contact = ode::dContact{..Default::default()};
-        contact.surface.mode = ode::dContactApprox1 as i32;
-        contact.surface.mu = ode::dInfinity;
-        if ode::dCollide(obj1, obj2, 1, &mut contact.geom, std::mem::size_of::<ode::dContact>() as libc::c_int) != 0 {
-            //println!("Collision detected!");
-            let joint = ode::dJointCreateContact(data.0, data.1, &mut contact);
+        // Statics touching statics (a box on the ground) can't move either way, and a contact
+        // without a body has nothing to report an event for.
+        if b1.is_null() && b2.is_null() {
+            return;
+        }
+        // Bodies held together by a joint already know where they stand relative to each other.
+        if !b1.is_null() && !b2.is_null() && joined(b1, b2) {
+            return;
+        }
+        // One point lets a box resting on another pivot around it, stacks need the corners.
+        let mut contacts: [ode::dContact; MAX_CONTACTS] = [Default::default(); MAX_CONTACTS];
+        let count = ode::dCollide(obj1, obj2, data.max_contacts as libc::c_int, &mut contacts[0].geom,
+                                  std::mem::size_of::<ode::dContact>() as libc::c_int);
+        if count <= 0 {
+            return;
+        }
+        let material = material::combine(&geom_material(obj1), &geom_material(obj2));
+        for contact in contacts[..count as usize].iter_mut() {
+            set_surface(&mut contact.surface, &material);
+            let joint = ode::dJointCreateContact(data.world, data.contact_group, contact);
             ode::dJointAttach(joint, b1, b2);
+            let mut c = Contact {
+                geom1: obj1,
+                geom2: obj2,
+                point: [contact.geom.pos[0], contact.geom.pos[1], contact.geom.pos[2]],
+                normal: [contact.geom.normal[0], contact.geom.normal[1], contact.geom.normal[2]],
+                feedback: Box::new(Default::default()),
+            };
+            ode::dJointSetFeedback(joint, &mut *c.feedback);
+            data.contacts.push(c);
+        }
+    }
+}
+
+// Like dAreConnectedExcluding contact joints, but that counts disabled joints too and broken
+// joints are disabled. Bodies a joint used to hold collide again.
+unsafe fn joined(b1: dBodyID, b2: dBodyID) -> bool {
+    for i in 0..ode::dBodyGetNumJoints(b1) {
+        let joint = ode::dBodyGetJoint(b1, i);
+        if ode::dJointIsEnabled(joint) != 0 && ode::dJointGetType(joint) as i32 != ode::dJointTypeContact as i32 &&
+           (ode::dJointGetBody(joint, 0) == b2 || ode::dJointGetBody(joint, 1) == b2) {
+            return true;
+        }
+    }
+    return false;
+}
+
+// Slower than this and a bouncy contact doesn't bounce, so resting things don't jitter.
+const BOUNCE_VELOCITY: f32 = 0.1;
+
+// Geom data points at the geom's Material in Simulation::materials, geoms without one get
+// the default.
+unsafe fn geom_material(geom: dGeomID) -> Material {
+    let material = ode::dGeomGetData(geom) as *const Material;
+    if material.is_null() {
+        return Material::default();
+    }
+    return *material;
+}
+
+fn set_surface(surface: &mut ode::dSurfaceParameters, material: &Material) {
+    let mut mode = ode::dContactApprox1 as i32;
+    surface.mu = if material.friction.is_infinite() { ode::dInfinity } else { material.friction };
+    if material.restitution > 0.0 {
+        mode |= ode::dContactBounce as i32;
+        surface.bounce = material.restitution;
+        surface.bounce_vel = BOUNCE_VELOCITY;
+    }
+    if let Some(erp) = material.soft_erp {
+        mode |= ode::dContactSoftERP as i32;
+        surface.soft_erp = erp;
+    }
+    if let Some(cfm) = material.soft_cfm {
+        mode |= ode::dContactSoftCFM as i32;
+        surface.soft_cfm = cfm;
+    }
+    if material.rolling_friction > 0.0 {
+        mode |= ode::dContactRolling as i32;
+        surface.rho = material.rolling_friction;
+        surface.rho2 = material.rolling_friction;
+    }
+    surface.mode = mode;
+}
+
+// What raycast hands ray_callback through dSpaceCollide2.
+struct RayQuery<'a> {
+    ray: dGeomID,
+    filter: &'a Fn(Option<usize>) -> bool,
+    nearest: Option<RayHit>,
+}
+
+extern fn ray_callback(data: *mut libc::c_void, obj1: ode::dGeomID, obj2: ode::dGeomID) {
+    unsafe {
+        let query = &mut *(data as *mut RayQuery);
+        let other = if obj1 == query.ray { obj2 } else { obj1 };
+        let body = body_index(dGeomGetBody(other));
+        if !(query.filter)(body) {
+            return;
+        }
+        let mut contact: ode::dContactGeom = Default::default();
+        if ode::dCollide(query.ray, other, 1, &mut contact, std::mem::size_of::<ode::dContactGeom>() as libc::c_int) == 0 {
+            return;
+        }
+        // For rays depth is the distance from the origin.
+        if query.nearest.map_or(true, |n| contact.depth < n.distance) {
+            query.nearest = Some(RayHit {
+                body: body,
+                point: Vec3::new(contact.pos[0], contact.pos[1], contact.pos[2]),
+                normal: Vec3::new(contact.normal[0], contact.normal[1], contact.normal[2]),
+                distance: contact.depth,
+            });
+        }
+    }
+}
+
+// What the overlap queries hand overlap_callback.
+struct OverlapQuery<'a> {
+    geom: dGeomID,
+    filter: &'a Fn(Option<usize>) -> bool,
+    hits: Vec<Option<usize>>,
+}
+
+extern fn overlap_callback(data: *mut libc::c_void, obj1: ode::dGeomID, obj2: ode::dGeomID) {
+    unsafe {
+        let query = &mut *(data as *mut OverlapQuery);
+        let other = if obj1 == query.geom { obj2 } else { obj1 };
+        let body = body_index(dGeomGetBody(other));
+        if !(query.filter)(body) {
+            return;
+        }
+        let mut contact: ode::dContactGeom = Default::default();
+        if ode::dCollide(query.geom, other, 1, &mut contact, std::mem::size_of::<ode::dContactGeom>() as libc::c_int) != 0 {
+            query.hits.push(body);
+        }
+    }
+}
+
+// Everything about a body that changes as the world runs.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct BodyState {
+    pub pos: [f32; 3],
+    pub quat: [f32; 4], // w, x, y, z as ODE stores it.
+    pub lin_vel: [f32; 3],
+    pub ang_vel: [f32; 3],
+    pub force: [f32; 3], // Accumulated since the last step, so inputs already applied survive.
+    pub torque: [f32; 3],
+    pub enabled: bool, // False once auto disable has put the body to sleep.
+    pub params: BodyParams,
+}
+
+// Set when the body is created and rarely touched after.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct BodyParams {
+    pub mass: f32,
+    pub center: [f32; 3], // Of mass, body relative.
+    pub inertia: [f32; 12], // 3 rows, 4 columns.
+    pub linear_damping: f32,
+    pub angular_damping: f32,
+    pub auto_disable: bool,
+    pub gravity: bool,
+}
+
+fn finite(name: &str, values: &[f32]) -> Result<(), String> {
+    if values.iter().all(|v| v.is_finite()) {
+        Ok(())
+    } else {
+        Err(format!("{} {:?} is not finite", name, values))
+    }
+}
+
+// Sylvester's criterion, for the 3x3 part of an ODE matrix (rows of 4).
+fn positive_definite(m: &[f64; 12]) -> bool {
+    let minor2 = m[0] * m[5] - m[1] * m[4];
+    let det = m[0] * (m[5] * m[10] - m[6] * m[9]) - m[1] * (m[4] * m[10] - m[6] * m[8]) + m[2] * (m[4] * m[9] - m[5] * m[8]);
+    m[0] > 0.0 && minor2 > 0.0 && det > 0.0
+}
+
+impl BodyState {
+    // States from saves and lockstep Starts come off disk and the network, and restore hands
+    // them to ODE as they are. ODE asserts on a zero quaternion and on a bad mass.
+    pub fn check(&self) -> Result<(), String> {
+        try!(finite("position", &self.pos));
+        try!(finite("rotation", &self.quat));
+        if self.quat.iter().all(|&q| q == 0.0) {
+            return Err("rotation is all zeros".to_string());
+        }
+        try!(finite("velocity", &self.lin_vel));
+        try!(finite("angular velocity", &self.ang_vel));
+        try!(finite("force", &self.force));
+        try!(finite("torque", &self.torque));
+        return self.params.check();
+    }
+}
+
+impl BodyParams {
+    // What dMassCheck looks at: a positive mass, and an inertia that is positive definite both
+    // as given and moved to the center of mass.
+    pub fn check(&self) -> Result<(), String> {
+        if !(self.mass > 0.0) || !self.mass.is_finite() {
+            return Err(format!("mass {} is not positive", self.mass));
+        }
+        try!(finite("center of mass", &self.center));
+        try!(finite("inertia", &self.inertia));
+        if !(self.linear_damping >= 0.0 && self.angular_damping >= 0.0) || !self.linear_damping.is_finite() ||
+           !self.angular_damping.is_finite() {
+            return Err(format!("damping {} {} is negative", self.linear_damping, self.angular_damping));
+        }
+        let mut inertia = [0.0f64; 12];
+        for (i, v) in self.inertia.iter().enumerate() {
+            inertia[i] = *v as f64;
+        }
+        if !positive_definite(&inertia) {
+            return Err(format!("inertia {:?} is not positive definite", self.inertia));
+        }
+        // I + mass * c^ c^ with c^ the cross product matrix of the center.
+        let c = [self.center[0] as f64, self.center[1] as f64, self.center[2] as f64];
+        let cross = [[0.0, -c[2], c[1]], [c[2], 0.0, -c[0]], [-c[1], c[0], 0.0]];
+        let mut moved = inertia;
+        for r in 0..3 {
+            for col in 0..3 {
+                let square: f64 = (0..3).map(|k| cross[r][k] * cross[k][col]).sum();
+                moved[r * 4 + col] += self.mass as f64 * square;
+            }
+        }
+        if !positive_definite(&moved) {
+            return Err(format!("inertia {:?} doesn't fit a center of mass at {:?}", self.inertia, self.center));
         }
+        return Ok(());
     }
 }
 
-fn fmt_3(e: [f32; 3]) -> String {
-    format!("({},{},{})", e[0], e[1], e[2])
+// Only means something for the Simulation that made it. Its index is what collision events,
+// ray hits, joints and the wire formats call the body.
+pub type BodyId = EntityId;
+
+// Things without a body, the ground is the y = 0 plane every world starts with.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StaticId {
+    Ground,
+    Box(usize),
+    Plane(usize),
+}
+
+// Where something is, for drawing.
+#[derive(Clone, Copy)]
+pub struct Pose {
+    pub position: [f32; 3],
+    pub rotation: [f32; 12], // 3 rows, 4 columns, like ODE.
 }
 
-fn fmt_12(e: [f32; 12]) -> String {
-    format!("({},{},{}),({},{},{}),({},{},{}),({},{},{})",
-            e[0], e[1], e[2], e[3], e[4], e[5], e[6], e[7], e[8], e[9], e[10], e[11])
+unsafe fn geom_pose(geom: dGeomID) -> Pose {
+    let rot = std::slice::from_raw_parts(ode::dGeomGetRotation(geom), 12);
+    let mut rotation = [0.0; 12];
+    rotation.copy_from_slice(rot);
+    Pose { position: read_3(ode::dGeomGetPosition(geom)), rotation: rotation }
+}
+
+struct SimBody {
+    geom: Geom, // Fields drop in order, the geom has to go before the rest.
+    body: Body,
+    trimesh_data: Option<TriMeshData>,
+    mass: dMass, // What was last set, ODE keeps its own copy.
+    meta: EntityMeta,
+}
+
+// What Simulation::bodies() walks over.
+pub struct Bodies<'a> {
+    iter: entity::Iter<'a, SimBody>,
+}
+
+impl<'a> Iterator for Bodies<'a> {
+    type Item = (BodyId, &'a EntityMeta);
+
+    fn next(&mut self) -> Option<(BodyId, &'a EntityMeta)> {
+        self.iter.next().map(|(id, b)| (id, &b.meta))
+    }
 }
 
+struct Joint {
+    id: dJointID,
+    kind: JointKind,
+    body1: usize,
+    body2: Option<usize>,
+    feedback: Box<dJointFeedback>, // ODE writes the joint's forces here every step.
+    break_force: Option<f32>,
+    broken: bool, // Disabled rather than destroyed, so a restore can bring it back as it was.
+}
+
+impl Drop for Joint {
+    fn drop(&mut self) {
+        unsafe {
+            ode::dJointDestroy(self.id);
+        }
+    }
+}
+
+// A body being pulled around by the mouse spring.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Drag {
+    pub body: usize,
+    pub target: [f32; 3],
+    pub stiffness: f32, // Per kg.
+    pub damping: f32,
+}
+
+// A copy of the whole world that restore() can put back, bodies in BodyId order.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WorldState {
+    pub paused: bool,
+    pub bodies: Vec<BodyState>,
+    #[serde(default)]
+    pub drags: Vec<Option<Drag>>, // By peer.
+    #[serde(default)]
+    pub params: Option<PhysicsParams>, // None in saves from before params could change.
+    #[serde(default)]
+    pub clock: Clock,
+    // ODE's random seed. QuickStep shuffles constraints with it, so a peer that rolled back
+    // has to have it back where it was too. It is global to ODE, not per world.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    // What each body looks like, so a save can make the ones the scene doesn't. Only
+    // save_state fills it in, rollback's copies every tick would be cloning meshes.
+    #[serde(default)]
+    pub shapes: Vec<Shape>,
+    // By JointId, true for the joints that have broken. Scene joints are made first and
+    // broken ones keep their slot, so the ids line up in every world built from the scene.
+    #[serde(default)]
+    pub broken: Vec<bool>,
+    // Pairs in contact, so collision events after a restore pick up where they were. Keyed by
+    // geom address, which means nothing outside this process, so saves go without and a
+    // loaded world starts with nothing touching.
+    #[serde(skip)]
+    pub touching: BTreeMap<PairKey, Touch>,
+}
+
+impl WorldState {
+    // For states from outside this process, see BodyState::check. Peers are a byte on the
+    // wire, so no more drags than that.
+    pub fn check(&self) -> Result<(), String> {
+        for (i, b) in self.bodies.iter().enumerate() {
+            try!(b.check().map_err(|e| format!("body {}: {}", i, e)));
+        }
+        if self.drags.len() > 256 {
+            return Err(format!("{} drags, more than there can be peers", self.drags.len()));
+        }
+        for d in self.drags.iter().filter_map(|d| d.as_ref()) {
+            try!(finite("drag target", &d.target));
+            if !(d.stiffness > 0.0 && d.damping >= 0.0) || !d.stiffness.is_finite() || !d.damping.is_finite() {
+                return Err(format!("drag stiffness {} and damping {} are not positive", d.stiffness, d.damping));
+            }
+        }
+        return clock::check_scale(self.clock.scale());
+    }
+}
+
+unsafe fn set_world_params(world: dWorldID, params: &PhysicsParams) {
+    ode::dWorldSetGravity(world, 0.0, params.gravity, 0.0);
+    ode::dWorldSetCFM(world, params.cfm);
+    ode::dWorldSetERP(world, params.erp);
+    ode::dWorldSetQuickStepNumIterations(world, params.iterations as libc::c_int);
+    ode::dWorldSetContactSurfaceLayer(world, params.contact_surface_layer);
+    ode::dWorldSetContactMaxCorrectingVel(world, if params.max_correcting_vel.is_infinite() { ode::dInfinity } else { params.max_correcting_vel });
+    ode::dWorldSetAutoDisableFlag(world, params.auto_disable as libc::c_int);
+    ode::dWorldSetAutoDisableLinearThreshold(world, params.auto_disable_linear);
+    ode::dWorldSetAutoDisableAngularThreshold(world, params.auto_disable_angular);
+    ode::dWorldSetAutoDisableSteps(world, params.auto_disable_steps as libc::c_int);
+    ode::dWorldSetAutoDisableTime(world, params.auto_disable_time);
+}
+
+// Set by create_body, None for geoms without a body.
+unsafe fn body_index(body: dBodyID) -> Option<usize> {
+    if body.is_null() {
+        return None;
+    }
+    return Some(ode::dBodyGetData(body) as usize);
+}
+
+unsafe fn read_3(p: *const dReal) -> [f32; 3] {
+    let s = std::slice::from_raw_parts(p, 3);
+    [s[0], s[1], s[2]]
+}
+
+// The ODE handles drop in field order, see ode_handle.
 pub struct Simulation {
-    world: dWorldID,
-    space: dSpaceID,
-    contact_group: dJointGroupID,
-    pub geoms: Vec<(dGeomID, Box<dMass>)>,
+    joints: Vec<Option<Joint>>, // None once destroyed or broken, so JointIds stay put.
+    drags: Vec<Option<(Drag, JointId)>>, // By peer, the joint is a springy ball joint to the world.
+    bodies: Entities<SimBody>, // Never removed from yet, so slots are dense and in creation order.
+    statics: Vec<(Geom, [f32; 3])>, // Boxes without a body and their half extents.
+    planes: Vec<Geom>,
+    ground: Geom,
+    materials: Vec<Box<Material>>, // Pointed to by geom data, boxed so pushing doesn't move them.
+    contact_group: JointGroup,
+    space: Space,
+    world: World,
+    _ode: Library,
+    pub player: usize, // Index of the cube inputs push around.
     paused: bool,
+    clock: Clock,
+    params: PhysicsParams,
+    rest_threshold: f32,
+    touching: BTreeMap<PairKey, Touch>,
+    events: Vec<CollisionEvent>, // From the last step.
+    next_net_id: u32,
 }
 
 impl Simulation {
-    pub fn init() -> Simulation {
-        let world;
-        let space;
-        let contact_group;
+    pub fn init(config: &PhysicsConfig) -> Simulation {
+        let library = Library::init();
+        let world = World::new();
+        let space = Space::new();
+        let ground;
+        let params = config.params();
+        if let Err(e) = params.check() {
+            panic!("Bad physics config: {}", e);
+        }
 
         unsafe {
-        ode::dInitODE();
-        world = ode::dWorldCreate();
-        space = ode::dHashSpaceCreate(std::ptr::null_mut());
-        ode::dWorldSetGravity(world, 0.0, -10.0, 0.0);
-        ode::dWorldSetCFM(world, 0.0001);
-        ode::dCreatePlane(space, 0.0, 1.0, 0.0, 0.0);
-        contact_group = ode::dJointGroupCreate(0);
+        set_world_params(world.id(), &params);
+        ode::dRandSetSeed(0); // Every peer's new world starts from the same one.
+        ground = Geom::from_id(ode::dCreatePlane(space.id(), 0.0, 1.0, 0.0, 0.0));
         }
 
         return Simulation{
-            world: world,
+            joints: Vec::new(),
+            drags: Vec::new(),
+            bodies: Entities::new(),
+            statics: Vec::new(),
+            planes: Vec::new(),
+            ground: ground,
+            materials: Vec::new(),
+            contact_group: JointGroup::new(),
             space: space,
-            contact_group: contact_group,
-            geoms: Vec::new(),
+            world: world,
+            _ode: library,
+            player: 0,
             paused: true,
+            clock: Clock::default(),
+            params: params,
+            rest_threshold: config.rest_threshold,
+            touching: BTreeMap::new(),
+            events: Vec::new(),
+            next_net_id: 0,
         };
     }
 
 
-    pub fn step(&mut self) {
-        if self.paused {
-            return
+    pub fn params(&self) -> PhysicsParams {
+        self.params
+    }
+
+    // Takes effect from the next step. In lockstep every peer has to make the same change
+    // before the same tick, send it with an Input rather than calling this directly.
+    pub fn set_params(&mut self, params: PhysicsParams) -> Result<(), String> {
+        try!(params.check());
+        let old = std::mem::replace(&mut self.params, params);
+        unsafe {
+            set_world_params(self.world.id(), &params);
+            if params.auto_disable != old.auto_disable || params.auto_disable_linear != old.auto_disable_linear ||
+               params.auto_disable_angular != old.auto_disable_angular || params.auto_disable_steps != old.auto_disable_steps ||
+               params.auto_disable_time != old.auto_disable_time {
+                // Bodies copy the world's settings when they are made, give them the new ones. Wake
+                // them too, with auto disable turned off nothing would wake the sleeping ones.
+                for (_, b) in self.bodies.iter() {
+                    ode::dBodySetAutoDisableDefaults(b.body.id());
+                    ode::dBodyEnable(b.body.id());
+                }
+            }
+        }
+        if params.step_size != old.step_size {
+            // The drag springs were worked out for the old step, make them again.
+            let drags: Vec<Option<Drag>> = self.drags.iter().map(|d| d.map(|(drag, _)| drag)).collect();
+            for (peer, drag) in drags.into_iter().enumerate() {
+                if let Some(d) = drag {
+                    self.release(peer);
+                    let body = self.body_at(d.body).unwrap();
+                    self.drag(peer, body, Vec3::new(d.target[0], d.target[1], d.target[2]), d.stiffness, d.damping);
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    // One tick, as many physics steps as the clock says. Returns how many that was.
+    pub fn step(&mut self) -> u32 {
+        let steps = self.advance_clock();
+        self.step_by(steps);
+        return steps;
+    }
+
+    // The clock half of step, how many steps this tick takes. Pauses once a run is over.
+    pub fn advance_clock(&mut self) -> u32 {
+        self.clock.advance(&mut self.paused)
+    }
+
+    // The stepping half, exactly that many steps whatever the clock or pause say. Snapshot
+    // clients step by what the server tells them.
+    pub fn step_by(&mut self, steps: u32) {
+        let mut events = Vec::new();
+        for _ in 0..steps {
+            self.step_once();
+            events.extend(self.events.drain(..));
         }
+        self.events = events;
+    }
+
+    fn step_once(&mut self) {
+        let mut collide = Collide { world: self.world.id(), contact_group: self.contact_group.id(), max_contacts: self.params.max_contacts,
+                                    contacts: Vec::new() };
         unsafe {
-        ode::dSpaceCollide(self.space, std::mem::transmute(&mut (self.world, self.contact_group)), near_callback); //Implicit that this function DOESNT change world.
-        ode::dWorldQuickStep(self.world, 0.01);
-        ode::dJointGroupEmpty(self.contact_group);
+        ode::dSpaceCollide(self.space.id(), std::mem::transmute(&mut collide), near_callback); //Implicit that this function DOESNT change world.
+        ode::dWorldQuickStep(self.world.id(), self.params.step_size);
+        ode::dJointGroupEmpty(self.contact_group.id());
         }
+        self.collect_events(collide.contacts);
+        self.break_joints();
     }
 
-    pub fn create_cube(&mut self, mass: f32, location: Vec3) {
-        let body;
+    // Collisions during the last tick, for game code to react to.
+    pub fn collision_events(&self) -> &[CollisionEvent] {
+        &self.events
+    }
+
+    fn collect_events(&mut self, contacts: Vec<Contact>) {
+        let mut now: BTreeMap<PairKey, (Touch, f32, usize)> = BTreeMap::new();
+        for c in contacts.iter() {
+            unsafe {
+                // Events always have a body first, flip the normal along with the geoms.
+                let (geom1, geom2, sign) = if dGeomGetBody(c.geom1).is_null() { (c.geom2, c.geom1, -1.0) } else { (c.geom1, c.geom2, 1.0) };
+                let normal = [c.normal[0] * sign, c.normal[1] * sign, c.normal[2] * sign];
+                let f = c.feedback.f1;
+                let impulse = (f[0] * c.normal[0] + f[1] * c.normal[1] + f[2] * c.normal[2]).abs() * self.params.step_size;
+                let key = (std::cmp::min(geom1 as usize, geom2 as usize), std::cmp::max(geom1 as usize, geom2 as usize));
+                let entry = now.entry(key).or_insert((Touch {
+                    body1: body_index(dGeomGetBody(geom1)).unwrap(),
+                    body2: body_index(dGeomGetBody(geom2)),
+                    point: [0.0; 3],
+                    normal: normal,
+                }, 0.0, 0));
+                for i in 0..3 {
+                    entry.0.point[i] += c.point[i];
+                }
+                entry.1 += impulse;
+                entry.2 += 1;
+            }
+        }
+        let now = now.into_iter().map(|(key, (mut touch, impulse, count))| {
+            for i in 0..3 {
+                touch.point[i] /= count as f32;
+            }
+            (key, (touch, impulse))
+        }).collect();
+        let mut touching = std::mem::replace(&mut self.touching, BTreeMap::new());
+        let events = {
+            let asleep = |t: &Touch| unsafe {
+                ode::dBodyIsEnabled(self.body(t.body1)) == 0 && t.body2.map_or(true, |b| ode::dBodyIsEnabled(self.body(b)) == 0)
+            };
+            collision::diff(&mut touching, now, asleep)
+        };
+        self.touching = touching;
+        self.events = events;
+    }
+
+    // A box reaching half_extents from its center along each axis.
+    pub fn create_cube(&mut self, mass: f32, half_extents: Vec3, location: Vec3) -> BodyId {
+        let size = [half_extents.x * 2.0, half_extents.y * 2.0, half_extents.z * 2.0];
+        self.create_body(Shape::Box { size: size }, mass, location)
+    }
+
+    // The shape has to pass Shape::check. Mass is spread evenly through it.
+    pub fn create_body(&mut self, shape: Shape, mass: f32, location: Vec3) -> BodyId {
+        let body = Body::new(&self.world);
         let geom;
-        let mut m: Box<ode::dMass>;
+        let mut m: ode::dMass = Default::default();
+        let mut trimesh_data = None;
+        let mut center = [0.0; 3]; // Of mass, from the shape's origin.
         unsafe {
-            body = ode::dBodyCreate(self.world);
-            geom = dCreateBox(self.space, 1.0, 1.0, 1.0);
-            m = Box::new(Default::default()); // Prevent mass from being free untill its actual owner drops it.
-            ode::dMassSetBox(&mut *m, mass, 1.0, 1.0, 1.0);
-            ode::dBodySetMass(body, &*m);
-            ode::dGeomSetBody(geom, body);
-            ode::dBodySetPosition(body, location.x, location.y, location.z);
+            let space = self.space.id();
+            geom = Geom::from_id(match shape {
+                Shape::Box { size } => {
+                    ode::dMassSetBoxTotal(&mut m, mass, size[0], size[1], size[2]);
+                    dCreateBox(space, size[0], size[1], size[2])
+                }
+                Shape::Sphere { radius } => {
+                    ode::dMassSetSphereTotal(&mut m, mass, radius);
+                    ode::dCreateSphere(space, radius)
+                }
+                Shape::Capsule { radius, length } => {
+                    ode::dMassSetCapsuleTotal(&mut m, mass, 3, radius, length); // 3 is the z axis.
+                    ode::dCreateCapsule(space, radius, length)
+                }
+                Shape::Cylinder { radius, length } => {
+                    ode::dMassSetCylinderTotal(&mut m, mass, 3, radius, length);
+                    ode::dCreateCylinder(space, radius, length)
+                }
+                Shape::Trimesh { ref vertices, ref triangles } => {
+                    let data = TriMeshData::build(vertices, triangles);
+                    let geom = ode::dCreateTriMesh(space, data.id(), None, None, None);
+                    trimesh_data = Some(data);
+                    ode::dMassSetTrimeshTotal(&mut m, mass, geom);
+                    // ODE wants the center of mass on the body origin, so the body sits there
+                    // and the mesh hangs off it, see the offset below.
+                    center = [m.c[0], m.c[1], m.c[2]];
+                    ode::dMassTranslate(&mut m, -center[0], -center[1], -center[2]);
+                    geom
+                }
+            });
+            ode::dBodySetMass(body.id(), &m);
+            ode::dGeomSetBody(geom.id(), body.id());
+            if center != [0.0; 3] {
+                // Geom poses (drawing, snapshots) stay where the shape's origin is.
+                ode::dGeomSetOffsetPosition(geom.id(), -center[0], -center[1], -center[2]);
+            }
+            ode::dBodySetPosition(body.id(), location.x + center[0], location.y + center[1], location.z + center[2]);
         }
 
-        self.geoms.push((geom, m));
-        println!("Created cube #{}", self.geoms.len());
+        println!("Created {} #{}", shape.name(), self.bodies.len() + 1);
+        let meta = EntityMeta::new(shape, self.next_net_id);
+        self.next_net_id += 1;
+        let body_id = body.id();
+        let id = self.bodies.insert(SimBody { geom: geom, body: body, trimesh_data: trimesh_data, mass: m, meta: meta });
+        unsafe {
+            ode::dBodySetData(body_id, id.index() as *mut libc::c_void); // For collision events.
+        }
+        return id;
+    }
+
+    pub fn body_count(&self) -> usize {
+        self.bodies.len()
     }
 
-    pub fn apply_force(&self, geom: dGeomID, force: Vec3) {
+    // Every body with its metadata, in index order.
+    pub fn bodies(&self) -> Bodies {
+        Bodies { iter: self.bodies.iter() }
+    }
+
+    // The body an index from a collision event, ray hit or packet means, if there is one.
+    pub fn body_at(&self, index: usize) -> Option<BodyId> {
+        self.bodies.id_at(index)
+    }
+
+    // None once the body is gone.
+    pub fn meta(&self, body: BodyId) -> Option<&EntityMeta> {
+        self.bodies.get(body).map(|b| &b.meta)
+    }
+
+    pub fn meta_mut(&mut self, body: BodyId) -> Option<&mut EntityMeta> {
+        self.bodies.get_mut(body).map(|b| &mut b.meta)
+    }
+
+    // Fixed for the life of the body, the geom was built from it.
+    pub fn shape(&self, body: BodyId) -> Option<&Shape> {
+        self.bodies.get(body).map(|b| b.meta.shape())
+    }
+
+    // Shapes in index order, what clients need to build bodies they haven't seen.
+    pub fn shapes(&self) -> Vec<Shape> {
+        self.bodies.iter().map(|(_, b)| b.meta.shape().clone()).collect()
+    }
+
+    // The body a grab off the wire means. Net ids are handed out in creation order and never
+    // reused, so this is the same body on every peer or none at all.
+    pub fn by_net_id(&self, net_id: u32) -> Option<BodyId> {
+        self.bodies.iter().find(|&(_, b)| b.meta.net_id() == net_id).map(|(id, _)| id)
+    }
+
+    fn sim_body(&self, body: BodyId) -> &SimBody {
+        self.bodies.get(body).expect("BodyId from another world")
+    }
+
+    // A box that never moves, walls and ramps for things to hit.
+    pub fn create_static_cube(&mut self, half_extents: Vec3, location: Vec3, quat: [f32; 4]) -> StaticId {
         unsafe {
-        let body = ode::dGeomGetBody(geom);
-        ode::dBodyAddForce(body, force.x, force.y, force.z);
+            let geom = Geom::from_id(dCreateBox(self.space.id(), half_extents.x * 2.0, half_extents.y * 2.0, half_extents.z * 2.0));
+            ode::dGeomSetPosition(geom.id(), location.x, location.y, location.z);
+            ode::dGeomSetQuaternion(geom.id(), &quat);
+            self.statics.push((geom, [half_extents.x, half_extents.y, half_extents.z]));
         }
+        return StaticId::Box(self.statics.len() - 1);
     }
 
-    pub fn get_location(&self, geom: dGeomID) -> Vec3 {
+    // ax + by + cz = d, solid on the side the normal points away from.
+    pub fn create_static_plane(&mut self, normal: Vec3, offset: f32) -> StaticId {
         unsafe {
-        let ppos = ode::dGeomGetPosition(geom);
-        let vec = std::slice::from_raw_parts(ppos, 3);
-        return Vec3 { x: vec[0], y: vec[1], z: vec[2] }
+            self.planes.push(Geom::from_id(ode::dCreatePlane(self.space.id(), normal.x, normal.y, normal.z, offset)));
+        }
+        return StaticId::Plane(self.planes.len() - 1);
+    }
+
+    // The static boxes and their half extents, for drawing. Planes go on forever so they aren't drawn.
+    pub fn static_boxes(&self) -> Vec<(Pose, [f32; 3])> {
+        self.statics.iter().map(|&(ref geom, half_extents)| (unsafe { geom_pose(geom.id()) }, half_extents)).collect()
+    }
+
+    fn static_geom(&self, id: StaticId) -> dGeomID {
+        match id {
+            StaticId::Ground => self.ground.id(),
+            StaticId::Box(i) => self.statics[i].0.id(),
+            StaticId::Plane(i) => self.planes[i].id(),
         }
     }
 
-    pub fn clean_up(&mut self) {
+    pub fn set_material(&mut self, body: BodyId, material: Material) {
+        let geom = self.sim_body(body).geom.id();
+        self.set_geom_material(geom, material);
+    }
+
+    pub fn set_static_material(&mut self, id: StaticId, material: Material) {
+        let geom = self.static_geom(id);
+        self.set_geom_material(geom, material);
+    }
+
+    fn set_geom_material(&mut self, geom: dGeomID, material: Material) {
         unsafe {
-        ode::dJointGroupDestroy(self.contact_group);
-        ode::dSpaceDestroy(self.space);
-        ode::dWorldDestroy(self.world);
-        ode::dCloseODE();
+            let current = ode::dGeomGetData(geom) as *mut Material;
+            if !current.is_null() {
+                *current = material;
+                return;
+            }
+            let mut boxed = Box::new(material);
+            ode::dGeomSetData(geom, &mut *boxed as *mut Material as *mut libc::c_void);
+            self.materials.push(boxed);
+        }
+    }
+
+    pub fn material(&self, body: BodyId) -> Material {
+        unsafe { geom_material(self.sim_body(body).geom.id()) }
+    }
+
+    pub fn set_rotation(&mut self, body: BodyId, quat: [f32; 4]) {
+        unsafe {
+            ode::dBodySetQuaternion(self.sim_body(body).body.id(), &quat);
+        }
+    }
+
+    pub fn set_velocity(&mut self, body: BodyId, linear: Vec3, angular: Vec3) {
+        let body = self.sim_body(body).body.id();
+        unsafe {
+            ode::dBodySetLinearVel(body, linear.x, linear.y, linear.z);
+            ode::dBodySetAngularVel(body, angular.x, angular.y, angular.z);
+        }
+    }
+
+    pub fn apply_force(&mut self, body: BodyId, force: Vec3) {
+        unsafe {
+        ode::dBodyAddForce(self.sim_body(body).body.id(), force.x, force.y, force.z);
+        }
+    }
+
+    // Joins body1 to body2, or to the world when there is no body2. Set the anchor and axis
+    // after, they are in world coordinates so the bodies should already be in place.
+    pub fn create_joint(&mut self, kind: JointKind, body1: BodyId, body2: Option<BodyId>) -> JointId {
+        let mut feedback: Box<dJointFeedback> = Box::new(Default::default());
+        let world = self.world.id();
+        let joint;
+        unsafe {
+            let id = match kind {
+                JointKind::Ball => ode::dJointCreateBall(world, std::ptr::null_mut()),
+                JointKind::Hinge => ode::dJointCreateHinge(world, std::ptr::null_mut()),
+                JointKind::Slider => ode::dJointCreateSlider(world, std::ptr::null_mut()),
+                JointKind::Fixed => ode::dJointCreateFixed(world, std::ptr::null_mut()),
+            };
+            ode::dJointSetFeedback(id, &mut *feedback);
+            joint = Joint { id: id, kind: kind, body1: body1.index(), body2: body2.map(|b| b.index()), feedback: feedback,
+                            break_force: None, broken: false };
+        }
+        // Reuse a slot a destroyed joint left behind, drags come and go all the time.
+        let joint = match self.joints.iter().position(|j| j.is_none()) {
+            Some(i) => {
+                self.joints[i] = Some(joint);
+                JointId(i)
+            }
+            None => {
+                self.joints.push(Some(joint));
+                JointId(self.joints.len() - 1)
+            }
+        };
+        self.attach_joint(joint, body1, body2);
+        return joint;
+    }
+
+    pub fn attach_joint(&mut self, joint: JointId, body1: BodyId, body2: Option<BodyId>) {
+        let b1 = self.sim_body(body1).body.id();
+        let b2 = body2.map_or(std::ptr::null_mut(), |b| self.sim_body(b).body.id());
+        if let Some(ref mut j) = self.joints[joint.0] {
+            j.body1 = body1.index();
+            j.body2 = body2.map(|b| b.index());
+            unsafe {
+                ode::dJointAttach(j.id, b1, b2);
+                if j.kind == JointKind::Fixed {
+                    ode::dJointSetFixed(j.id); // Fixed joints hold whatever offset they are attached at.
+                }
+            }
+        }
+    }
+
+    // Ball and hinge joints turn around this point, sliders and fixed joints have none.
+    pub fn set_joint_anchor(&mut self, joint: JointId, anchor: Vec3) {
+        if let Some(ref j) = self.joints[joint.0] {
+            unsafe {
+                match j.kind {
+                    JointKind::Ball => ode::dJointSetBallAnchor(j.id, anchor.x, anchor.y, anchor.z),
+                    JointKind::Hinge => ode::dJointSetHingeAnchor(j.id, anchor.x, anchor.y, anchor.z),
+                    JointKind::Slider | JointKind::Fixed => (),
+                }
+            }
+        }
+    }
+
+    // Hinges turn around it, sliders move along it.
+    pub fn set_joint_axis(&mut self, joint: JointId, axis: Vec3) {
+        if let Some(ref j) = self.joints[joint.0] {
+            unsafe {
+                match j.kind {
+                    JointKind::Hinge => ode::dJointSetHingeAxis(j.id, axis.x, axis.y, axis.z),
+                    JointKind::Slider => ode::dJointSetSliderAxis(j.id, axis.x, axis.y, axis.z),
+                    JointKind::Ball | JointKind::Fixed => (),
+                }
+            }
+        }
+    }
+
+    fn set_joint_param(&mut self, joint: JointId, param: i32, value: f32) {
+        if let Some(ref j) = self.joints[joint.0] {
+            unsafe {
+                match j.kind {
+                    JointKind::Hinge => ode::dJointSetHingeParam(j.id, param, value),
+                    JointKind::Slider => ode::dJointSetSliderParam(j.id, param, value),
+                    JointKind::Ball | JointKind::Fixed => (),
+                }
+            }
+        }
+    }
+
+    // Radians for hinges, distance along the axis for sliders. Ball and fixed joints have none.
+    pub fn set_joint_limits(&mut self, joint: JointId, low: f32, high: f32) {
+        self.set_joint_param(joint, ode::dParamLoStop as i32, low);
+        self.set_joint_param(joint, ode::dParamHiStop as i32, high);
+    }
+
+    // Drives a hinge or slider toward `velocity`, pushing with at most `max_force`. A max_force
+    // of 0 turns the motor off.
+    pub fn set_joint_motor(&mut self, joint: JointId, velocity: f32, max_force: f32) {
+        self.set_joint_param(joint, ode::dParamVel as i32, velocity);
+        self.set_joint_param(joint, ode::dParamFMax as i32, max_force);
+    }
+
+    // The joint breaks after any step where it pulled on body1 harder than this. Broken
+    // joints are disabled, not destroyed, and restore() enables them again.
+    pub fn set_joint_break_force(&mut self, joint: JointId, force: Option<f32>) {
+        if let Some(ref mut j) = self.joints[joint.0] {
+            j.break_force = force;
+        }
+    }
+
+    pub fn destroy_joint(&mut self, joint: JointId) {
+        self.joints[joint.0] = None; // Dropping it destroys it.
+    }
+
+    // Pulls body's center towards target with a spring, taking over from whatever `peer` was
+    // dragging before. Call every tick the target moves.
+    pub fn drag(&mut self, peer: usize, body: BodyId, target: Vec3, stiffness: f32, damping: f32) {
+        let drag = Drag { body: body.index(), target: [target.x, target.y, target.z], stiffness: stiffness, damping: damping };
+        if self.drags.len() <= peer {
+            self.drags.resize(peer + 1, None);
+        }
+        let held = match self.drags[peer] {
+            Some((old, joint)) if old.body == body.index() && old.stiffness == stiffness && old.damping == damping => Some(joint),
+            _ => None,
+        };
+        let joint = match held {
+            Some(joint) => joint,
+            None => {
+                self.release(peer);
+                let joint = self.create_joint(JointKind::Ball, body, None);
+                let center = self.position(body);
+                self.set_joint_anchor(joint, center);
+                // A joint with ERP and CFM set like this acts as a damped spring, see the ODE
+                // manual. Scaled by mass so everything follows the mouse about as quickly.
+                let (h, mass) = (self.params.step_size, self.sim_body(body).mass.mass);
+                let (kp, kd) = (stiffness * mass, damping * mass);
+                let id = self.joints[joint.0].as_ref().unwrap().id;
+                unsafe {
+                    ode::dJointSetBallParam(id, ode::dParamERP as i32, h * kp / (h * kp + kd));
+                    ode::dJointSetBallParam(id, ode::dParamCFM as i32, 1.0 / (h * kp + kd));
+                }
+                joint
+            }
+        };
+        let id = self.joints[joint.0].as_ref().unwrap().id;
+        unsafe {
+            ode::dJointSetBallAnchor2(id, target.x, target.y, target.z);
+            ode::dBodyEnable(self.sim_body(body).body.id());
+        }
+        self.drags[peer] = Some((drag, joint));
+        self.update_owner(body.index());
+    }
+
+    // Lets go, the body flies off with whatever speed the spring gave it.
+    pub fn release(&mut self, peer: usize) {
+        if let Some(Some((drag, joint))) = self.drags.get(peer).cloned() {
+            self.destroy_joint(joint);
+            self.drags[peer] = None;
+            self.update_owner(drag.body);
+        }
+    }
+
+    // The lowest peer dragging it owns a body. Only depends on the drags, not the order they
+    // came in, so restores and replays agree with a straight run.
+    fn update_owner(&mut self, index: usize) {
+        let owner = self.drags.iter().position(|d| d.map_or(false, |(drag, _)| drag.body == index));
+        if let Some(body) = self.body_at(index) {
+            self.bodies.get_mut(body).unwrap().meta.owner = owner;
+        }
+    }
+
+    pub fn dragged(&self, peer: usize) -> Option<BodyId> {
+        self.drags.get(peer).and_then(|d| d.and_then(|(drag, _)| self.body_at(drag.body)))
+    }
+
+    fn break_joints(&mut self) {
+        let mut broken = Vec::new();
+        for (i, joint) in self.joints.iter().enumerate() {
+            if let Some(ref j) = *joint {
+                if let Some(limit) = j.break_force {
+                    let f = j.feedback.f1;
+                    if !j.broken && (f[0]*f[0] + f[1]*f[1] + f[2]*f[2]).sqrt() > limit {
+                        broken.push(i);
+                    }
+                }
+            }
+        }
+        for i in broken {
+            println!("Joint {} broke.", i);
+            self.set_joint_broken(JointId(i), true);
+        }
+    }
+
+    // Disabling only sets a flag, the joint keeps its anchors exactly and its place in ODE's
+    // joint list, so enabling it again is as if it never broke. Detaching it would have ODE
+    // work the anchors out again from where the bodies are now.
+    fn set_joint_broken(&mut self, joint: JointId, broken: bool) {
+        if let Some(ref mut j) = self.joints[joint.0] {
+            j.broken = broken;
+            unsafe {
+                if broken {
+                    ode::dJointDisable(j.id);
+                } else {
+                    ode::dJointEnable(j.id);
+                }
+            }
+        }
+    }
+
+    pub fn joint_lines(&self) -> Vec<JointLine> {
+        let mut lines = Vec::new();
+        for joint in self.joints.iter() {
+            if let Some(ref j) = *joint {
+                if j.broken {
+                    continue;
+                }
+                let mut anchor: dVector3 = [0.0; 4];
+                unsafe {
+                    match j.kind {
+                        JointKind::Ball => ode::dJointGetBallAnchor(j.id, &mut anchor),
+                        JointKind::Hinge => ode::dJointGetHingeAnchor(j.id, &mut anchor),
+                        // No anchor, join them at the middle.
+                        JointKind::Slider | JointKind::Fixed => {
+                            let p1 = self.position(self.body_at(j.body1).unwrap());
+                            let p2 = match j.body2 {
+                                Some(b) => self.position(self.body_at(b).unwrap()),
+                                None => p1,
+                            };
+                            anchor = [(p1.x + p2.x) / 2.0, (p1.y + p2.y) / 2.0, (p1.z + p2.z) / 2.0, 0.0];
+                        }
+                    }
+                }
+                lines.push(JointLine { body1: j.body1, body2: j.body2, anchor: [anchor[0], anchor[1], anchor[2]] });
+            }
+        }
+        return lines;
+    }
+
+    // World space line segments for drawing, lines for bodies we don't have are skipped.
+    pub fn joint_segments(&self, lines: &[JointLine]) -> Vec<([f32; 3], [f32; 3])> {
+        let mut segments = Vec::new();
+        for line in lines.iter() {
+            for &body in [Some(line.body1), line.body2].iter() {
+                if let Some(b) = body {
+                    if let Some(id) = self.body_at(b) {
+                        let p = self.position(id);
+                        segments.push(([p.x, p.y, p.z], line.anchor));
+                    }
+                }
+            }
+        }
+        return segments;
+    }
+
+    // The first thing along the ray within max_dist that the filter lets through. dir doesn't
+    // have to be normalized.
+    pub fn raycast<F>(&self, origin: Vec3, dir: Vec3, max_dist: f32, filter: F) -> Option<RayHit>
+        where F: Fn(Option<usize>) -> bool {
+        let dir = dir.normalize();
+        let ray = unsafe { Geom::from_id(ode::dCreateRay(std::ptr::null_mut(), max_dist)) };
+        let mut query = RayQuery { ray: ray.id(), filter: &filter, nearest: None };
+        unsafe {
+            ode::dGeomRaySet(query.ray, origin.x, origin.y, origin.z, dir.x, dir.y, dir.z);
+            ode::dGeomRaySetClosestHit(query.ray, 1); // Trimeshes give any hit otherwise.
+            ode::dSpaceCollide2(query.ray, self.space.id() as dGeomID, &mut query as *mut RayQuery as *mut libc::c_void, ray_callback);
+        }
+        // ODE's normal depends on which geom it thinks came first.
+        return query.nearest.map(|mut hit| {
+            if hit.normal.dot(dir) > 0.0 {
+                hit.normal = -hit.normal;
+            }
+            hit
+        });
+    }
+
+    // Bodies the sphere overlaps that the filter lets through, in BodyId order. Statics and the
+    // ground all come back as one None, ahead of the bodies.
+    pub fn overlap_sphere<F>(&self, center: Vec3, radius: f32, filter: F) -> Vec<Option<usize>>
+        where F: Fn(Option<usize>) -> bool {
+        unsafe {
+            let geom = Geom::from_id(ode::dCreateSphere(std::ptr::null_mut(), radius));
+            ode::dGeomSetPosition(geom.id(), center.x, center.y, center.z);
+            return self.overlap(&geom, &filter);
+        }
+    }
+
+    // Like overlap_sphere, for a box with these half extents turned by quat (w, x, y, z).
+    pub fn overlap_box<F>(&self, center: Vec3, half_extents: Vec3, quat: [f32; 4], filter: F) -> Vec<Option<usize>>
+        where F: Fn(Option<usize>) -> bool {
+        unsafe {
+            let geom = Geom::from_id(dCreateBox(std::ptr::null_mut(), half_extents.x * 2.0, half_extents.y * 2.0, half_extents.z * 2.0));
+            ode::dGeomSetPosition(geom.id(), center.x, center.y, center.z);
+            ode::dGeomSetQuaternion(geom.id(), &quat);
+            return self.overlap(&geom, &filter);
+        }
+    }
+
+    // The geom mustn't be in a space.
+    unsafe fn overlap(&self, geom: &Geom, filter: &Fn(Option<usize>) -> bool) -> Vec<Option<usize>> {
+        let mut query = OverlapQuery { geom: geom.id(), filter: filter, hits: Vec::new() };
+        ode::dSpaceCollide2(geom.id(), self.space.id() as dGeomID, &mut query as *mut OverlapQuery as *mut libc::c_void, overlap_callback);
+        let mut hits = query.hits;
+        hits.sort();
+        hits.dedup();
+        return hits;
+    }
+
+    fn body(&self, index: usize) -> dBodyID {
+        self.sim_body(self.body_at(index).unwrap()).body.id()
+    }
+
+    pub fn position(&self, body: BodyId) -> Vec3 {
+        let p = self.pose(body).position;
+        return Vec3 { x: p[0], y: p[1], z: p[2] }
+    }
+
+    pub fn pose(&self, body: BodyId) -> Pose {
+        unsafe { geom_pose(self.sim_body(body).geom.id()) }
+    }
+
+    // Cheap enough to call every tick, rollback keeps a ring of these.
+    pub fn snapshot(&self) -> WorldState {
+        let mut bodies = Vec::with_capacity(self.bodies.len());
+        for (_, b) in self.bodies.iter() {
+            unsafe {
+                let body = b.body.id();
+                let q = std::slice::from_raw_parts(ode::dBodyGetQuaternion(body), 4);
+                let mut m: ode::dMass = Default::default();
+                ode::dBodyGetMass(body, &mut m);
+                bodies.push(BodyState {
+                    pos: read_3(ode::dBodyGetPosition(body)),
+                    quat: [q[0], q[1], q[2], q[3]],
+                    lin_vel: read_3(ode::dBodyGetLinearVel(body)),
+                    ang_vel: read_3(ode::dBodyGetAngularVel(body)),
+                    force: read_3(ode::dBodyGetForce(body)),
+                    torque: read_3(ode::dBodyGetTorque(body)),
+                    enabled: ode::dBodyIsEnabled(body) != 0,
+                    params: BodyParams {
+                        mass: m.mass,
+                        center: [m.c[0], m.c[1], m.c[2]],
+                        inertia: m.I,
+                        linear_damping: ode::dBodyGetLinearDamping(body),
+                        angular_damping: ode::dBodyGetAngularDamping(body),
+                        auto_disable: ode::dBodyGetAutoDisableFlag(body) != 0,
+                        gravity: ode::dBodyGetGravityMode(body) != 0,
+                    },
+                });
+            }
+        }
+        let drags = self.drags.iter().map(|d| d.map(|(drag, _)| drag)).collect();
+        let broken = self.joints.iter().map(|j| j.as_ref().map_or(false, |j| j.broken)).collect();
+        let seed = unsafe { ode::dRandGetSeed() as u64 };
+        return WorldState { paused: self.paused, bodies: bodies, drags: drags, params: Some(self.params), clock: self.clock,
+                            seed: Some(seed), shapes: Vec::new(), broken: broken, touching: self.touching.clone() };
+    }
+
+    // A snapshot with every body's shape, for saves and anything else that builds the world
+    // again from nothing.
+    pub fn save_state(&self) -> WorldState {
+        let mut state = self.snapshot();
+        state.shapes = self.shapes();
+        return state;
+    }
+
+    // The state has to come from this simulation (or one built the same way), bodies are
+    // matched up by index. Parameters are only written back when they changed, setting the
+    // mass is not free.
+    pub fn restore(&mut self, state: &WorldState) {
+        assert_eq!(state.bodies.len(), self.bodies.len(), "World state is for a different scene");
+        self.paused = state.paused;
+        self.clock = state.clock;
+        self.touching = state.touching.clone();
+        if let Some(seed) = state.seed {
+            unsafe {
+                ode::dRandSetSeed(seed as libc::c_ulong);
+            }
+        }
+        // Before the bodies, changing auto disable resets theirs.
+        if let Some(params) = state.params {
+            if params != self.params {
+                if let Err(e) = self.set_params(params) {
+                    println!("Keeping our physics params, the restored ones are bad: {}", e);
+                }
+            }
+        }
+        for ((_, sim_body), b) in self.bodies.iter_mut().zip(state.bodies.iter()) {
+            unsafe {
+                let body = sim_body.body.id();
+                let m = &mut sim_body.mass;
+                let p = &b.params;
+                if m.mass != p.mass || m.c[0] != p.center[0] || m.c[1] != p.center[1] ||
+                   m.c[2] != p.center[2] || m.I != p.inertia {
+                    m.mass = p.mass;
+                    m.c[0] = p.center[0];
+                    m.c[1] = p.center[1];
+                    m.c[2] = p.center[2];
+                    m.I = p.inertia;
+                    ode::dBodySetMass(body, m);
+                }
+                ode::dBodySetLinearDamping(body, p.linear_damping);
+                ode::dBodySetAngularDamping(body, p.angular_damping);
+                ode::dBodySetAutoDisableFlag(body, p.auto_disable as i32);
+                ode::dBodySetGravityMode(body, p.gravity as i32);
+
+                ode::dBodySetPosition(body, b.pos[0], b.pos[1], b.pos[2]);
+                ode::dBodySetQuaternion(body, &b.quat);
+                ode::dBodySetLinearVel(body, b.lin_vel[0], b.lin_vel[1], b.lin_vel[2]);
+                ode::dBodySetAngularVel(body, b.ang_vel[0], b.ang_vel[1], b.ang_vel[2]);
+                ode::dBodySetForce(body, b.force[0], b.force[1], b.force[2]);
+                ode::dBodySetTorque(body, b.torque[0], b.torque[1], b.torque[2]);
+                if b.enabled {
+                    ode::dBodyEnable(body);
+                } else {
+                    ode::dBodyDisable(body);
+                }
+            }
+        }
+        // Joints that broke since come back, ones that broke before break again. Only scene
+        // joints break, drags never have a break force.
+        for i in 0..self.joints.len() {
+            let broken = state.broken.get(i).cloned().unwrap_or(false);
+            if self.joints[i].as_ref().map_or(false, |j| j.broken != broken) {
+                self.set_joint_broken(JointId(i), broken);
+            }
+        }
+        // Drag joints aren't bodies, put back the ones that were held then. Joints that are
+        // already right are left alone, making them again would move them in ODE's joint list
+        // and change the order the solver sees them in.
+        for peer in 0..std::cmp::max(self.drags.len(), state.drags.len()) {
+            let want = state.drags.get(peer).cloned().unwrap_or(None);
+            let have = self.drags.get(peer).cloned().unwrap_or(None).map(|(drag, _)| drag);
+            if want == have {
+                continue;
+            }
+            match want.and_then(|d| self.body_at(d.body).map(|body| (d, body))) {
+                // Keeps the joint when only the target moved.
+                Some((d, body)) => self.drag(peer, body, Vec3::new(d.target[0], d.target[1], d.target[2]), d.stiffness, d.damping),
+                None => self.release(peer),
+            }
         }
     }
 
@@ -131,25 +1237,26 @@ impl Simulation {
         buf.write_u8(self.paused as u8).unwrap();
         buf.write_u8(init as u8).unwrap();
         if !self.paused || init {
-            buf.write_u32::<LittleEndian>(self.geoms.len() as u32).unwrap();
+            buf.write_u32::<LittleEndian>(self.bodies.len() as u32).unwrap();
             //let mut print = true;
-            for &(geom, ref m) in self.geoms.iter() {
+            for (_, b) in self.bodies.iter() {
+                let geom = b.geom.id();
+                let m = &b.mass;
                 let pos;
                 let rot;
                 let vel;
                 unsafe {
                 pos = std::slice::from_raw_parts(ode::dGeomGetPosition(geom), 3);
                 rot = std::slice::from_raw_parts(ode::dGeomGetRotation(geom), 12); // 3 rows, 4 columns.
-                let body = dGeomGetBody(geom);
-                vel = std::slice::from_raw_parts(ode::dBodyGetLinearVel(body), 3);
+                vel = std::slice::from_raw_parts(ode::dBodyGetLinearVel(b.body.id()), 3);
                 //if print {
                     //println!("Vel: {}, {}, {}", vel[0], vel[1], vel[2]);
                     //print = false;
                 //}
                 }
-                if vel[0].abs() <= 0.1f32 &&
-                   vel[1].abs() <= 0.1f32 &&
-                   vel[2].abs() <= 0.1f32 &&
+                if vel[0].abs() <= self.rest_threshold &&
+                   vel[1].abs() <= self.rest_threshold &&
+                   vel[2].abs() <= self.rest_threshold &&
                    !init {
                     buf.write_u8(0).unwrap(); // Cube at rest, probably fine to not send.
                 } else {
@@ -171,46 +1278,74 @@ impl Simulation {
     }
 
     pub fn deserialize(&mut self, buf: &[u8]) {
-        let mut input = Cursor::new(buf);
-        let is_paused = input.read_u8().unwrap() != 0u8;
-        let is_init = input.read_u8().unwrap() != 0u8;
-
-        self.paused = is_paused;
-        if !self.paused || is_init {
-            let num_geoms = input.read_u32::<LittleEndian>().unwrap() as usize;
-            //println!("Decoding {} geoms",num_geoms);
-            for i in 0..num_geoms{
-                if input.read_u8().unwrap() == 0 {
-                    continue; //This cube has no velocity and it is not an init frame
-                              // so no data for it follows.
-                }
+        match packet::decode(buf) {
+            Ok(snapshot) => self.apply_snapshot(&snapshot),
+            Err(e) => println!("Dropping bad snapshot: {}", e),
+        }
+    }
 
-                let mut pos = [0f32; 3];
-                let mut rot = [0f32; 12];
-                for p in 0..3 {
-                    pos[p] = input.read_f32::<LittleEndian>().unwrap();
-                }
-                for r in 0..12 {
-                    rot[r] = input.read_f32::<LittleEndian>().unwrap();
-                }
-                let mut mass = 1.0;
-                if is_init {
-                    mass = input.read_f32::<LittleEndian>().unwrap();
-                }
-                if i == self.geoms.len() {
-                    self.create_cube(mass, Vec3::new(pos[0], pos[1], pos[2]));
-                }
-                unsafe {
-                    dGeomSetPosition(self.geoms[i].0, pos[0], pos[1], pos[2]);
-                    dGeomSetRotation(self.geoms[i].0, &rot);
-                }
+    pub fn apply_snapshot(&mut self, snapshot: &packet::Snapshot) {
+        self.paused = snapshot.paused;
+        // Init packets say what bodies we don't have yet look like, without it they are cubes.
+        let shapes = match snapshot.extension(packet::EXT_SHAPES).map(packet::decode_shapes) {
+            Some(Ok(shapes)) => shapes,
+            Some(Err(e)) => {
+                println!("Ignoring bad shapes in snapshot: {}", e);
+                Vec::new()
+            }
+            None => Vec::new(),
+        };
+        //println!("Decoding {} geoms", snapshot.cubes.len());
+        for (i, cube) in snapshot.cubes.iter().enumerate() {
+            let cube = match *cube {
+                Some(ref c) => c,
+                None => continue, // At rest, nothing sent for it.
+            };
+            let pos = cube.pos;
+            if i > self.bodies.len() {
+                println!("Snapshot moves cube {} but we only know of {}, dropping the rest.", i, self.bodies.len());
+                return;
+            }
+            if i == self.bodies.len() {
+                let shape = shapes.get(i).cloned().unwrap_or_default();
+                self.create_body(shape, cube.mass.unwrap_or(1.0), Vec3::new(pos[0], pos[1], pos[2]));
+            }
+            let geom = self.sim_body(self.body_at(i).unwrap()).geom.id();
+            unsafe {
+                dGeomSetPosition(geom, pos[0], pos[1], pos[2]);
+                dGeomSetRotation(geom, &cube.rot);
             }
         }
     }
 
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
     pub fn toggle_pause(&mut self) {
         self.paused = !self.paused;
     }
 
+    // The next tick takes one step, when paused.
+    pub fn single_step(&mut self) {
+        self.clock.single_step();
+    }
+
+    // Unpauses for that many steps, 0 cancels a run that is still going.
+    pub fn run_for(&mut self, steps: u32) {
+        self.clock.run(steps);
+        if steps > 0 {
+            self.paused = false;
+        }
+    }
+
+    pub fn time_scale(&self) -> f32 {
+        self.clock.scale()
+    }
+
+    pub fn set_time_scale(&mut self, scale: f32) -> Result<(), String> {
+        self.clock.set_scale(scale)
+    }
+
 
 }
@@ -0,0 +1,42 @@
+extern crate rust_network;
+
+use std::fs;
+use std::io::prelude::*;
+use std::path::Path;
+use rust_network::fuzz;
+
+// Every file under fuzz/regressions/<target>/ goes through that target once. When cargo fuzz
+// finds a crash, copy it out of fuzz/artifacts/<target>/ into here so it stays fixed.
+fn replay(target: &str, run: fn(&[u8])) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/regressions").join(target);
+    let mut count = 0;
+    for entry in fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        let mut data = Vec::new();
+        fs::File::open(&path).unwrap().read_to_end(&mut data).unwrap();
+        println!("Replaying {}", path.display());
+        run(&data);
+        count += 1;
+    }
+    assert!(count > 0, "no inputs in {}", dir.display());
+}
+
+#[test]
+fn decode_snapshot_regressions() {
+    replay("decode_snapshot", fuzz::decode_snapshot);
+}
+
+#[test]
+fn deserialize_regressions() {
+    replay("deserialize", fuzz::deserialize);
+}
+
+#[test]
+fn decode_lockstep_regressions() {
+    replay("decode_lockstep", fuzz::decode_lockstep);
+}
+
+#[test]
+fn decode_checksum_regressions() {
+    replay("decode_checksum", fuzz::decode_checksum);
+}
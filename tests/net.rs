@@ -0,0 +1,67 @@
+extern crate rust_network;
+
+use std::net::SocketAddr;
+use std::thread;
+use std::time::Duration;
+use rust_network::net;
+
+// A dual-stack server socket echoes one datagram back, the client side goes through connect
+// the same way the client binary does.
+fn round_trip(host: &str) {
+    let server = net::bind_dual_stack(0).unwrap();
+    let port = server.local_addr().unwrap().port();
+    server.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let echo = thread::spawn(move || {
+        let mut buf = [0; 64];
+        let (amt, from) = server.recv_from(&mut buf).unwrap();
+        server.send_to(&buf[..amt], from).unwrap();
+        from
+    });
+
+    let (socket, addr, reply) = net::connect(host, port, 0, b"hello").unwrap();
+    assert_eq!(reply, b"hello".to_vec());
+    let from = echo.join().unwrap();
+    // IPv4 peers arrive as ::ffff:127.0.0.1 on the dual-stack socket.
+    assert_eq!(net::canonical(from).ip(), addr.ip());
+    assert_eq!(from.port(), socket.local_addr().unwrap().port());
+}
+
+#[test]
+fn round_trip_ipv4_loopback() {
+    round_trip("127.0.0.1");
+}
+
+#[test]
+fn round_trip_ipv6_loopback() {
+    round_trip("::1");
+}
+
+#[test]
+fn mapped_addresses_are_the_same_peer() {
+    let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+    let mapped = addr("[::ffff:192.0.2.7]:4000");
+    let v4 = addr("192.0.2.7:4000");
+    assert_eq!(net::canonical(mapped), v4);
+    assert_eq!(net::canonical(v4), v4);
+    assert!(net::same_peer(mapped, v4));
+    assert!(net::same_peer(v4, mapped));
+    assert!(!net::same_peer(mapped, addr("192.0.2.7:4001")));
+    assert!(!net::same_peer(mapped, addr("192.0.2.8:4000")));
+
+    // Only ::ffff:0:0/96 is IPv4, other IPv6 addresses stay as they are.
+    let v6 = addr("[::1]:4000");
+    assert_eq!(net::canonical(v6), v6);
+    assert!(!net::same_peer(v6, addr("127.0.0.1:4000")));
+    let compatible = addr("[::192.0.2.7]:4000");
+    assert_eq!(net::canonical(compatible), compatible);
+}
+
+#[test]
+fn resolve_address_forms() {
+    let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+    assert_eq!(net::resolve("127.0.0.1", 35555).unwrap(), vec![addr("127.0.0.1:35555")]);
+    assert_eq!(net::resolve("127.0.0.1:40", 35555).unwrap(), vec![addr("127.0.0.1:40")]);
+    assert_eq!(net::resolve("::1", 35555).unwrap(), vec![addr("[::1]:35555")]);
+    assert_eq!(net::resolve("[::1]", 35555).unwrap(), vec![addr("[::1]:35555")]);
+    assert_eq!(net::resolve("[::1]:40", 35555).unwrap(), vec![addr("[::1]:40")]);
+}
@@ -0,0 +1,101 @@
+extern crate rust_network;
+
+use rust_network::config::PhysicsConfig;
+use rust_network::desync::Checksum;
+use rust_network::simulation::Simulation;
+use rust_network::scene::Scene;
+use rust_network::vec::Vec3;
+
+// A heap of cubes dropped on each other, so there are plenty of contacts for QuickStep to
+// shuffle.
+fn pile() -> Simulation {
+    let mut simulation = Simulation::init(&PhysicsConfig::default());
+    for i in 0..20 {
+        let x = (i % 3) as f32 * 0.7 - 0.7;
+        let z = ((i / 3) % 3) as f32 * 0.7 - 0.7;
+        simulation.create_cube(1.0, Vec3::new(0.5, 0.5, 0.5), Vec3::new(x, 1.0 + i as f32 * 0.6, z));
+    }
+    simulation.toggle_pause();
+    return simulation;
+}
+
+// What a rollback peer does: go back to a saved tick and simulate forward again. It has to
+// land exactly where a peer that never went back is.
+#[test]
+fn restore_and_replay_matches() {
+    let mut simulation = pile();
+    for _ in 0..30 {
+        simulation.step();
+    }
+    let saved = simulation.snapshot();
+    for _ in 0..90 {
+        simulation.step();
+    }
+    let straight = Checksum::of(120, &simulation.snapshot());
+
+    // Carry on past it first, like a peer that predicted ahead before hearing the real inputs.
+    for _ in 0..45 {
+        simulation.step();
+    }
+    simulation.restore(&saved);
+    for _ in 0..90 {
+        simulation.step();
+    }
+    let replayed = Checksum::of(120, &simulation.snapshot());
+    assert_eq!(straight, replayed);
+}
+
+// A weight hanging from a joint that holds it but not a hard yank.
+const HANGING: &'static str = r#"{
+    "bodies": [{ "mass": 1, "position": [0, 5, 0] }],
+    "joints": [{ "type": "ball", "body1": 0, "anchor": [0, 5, 0], "break_force": 20 }]
+}"#;
+
+fn hanging() -> Simulation {
+    let mut simulation = Simulation::init(&PhysicsConfig::default());
+    Scene::parse(HANGING).unwrap().build(&mut simulation);
+    simulation.toggle_pause();
+    return simulation;
+}
+
+// A peer that predicted an input that broke the joint has to get the joint back when it rolls
+// back, or it never agrees with the peers that didn't.
+#[test]
+fn restore_mends_broken_joints() {
+    let mut straight = hanging();
+    for _ in 0..60 {
+        straight.step();
+    }
+
+    let mut simulation = hanging();
+    let saved = simulation.snapshot();
+    let weight = simulation.body_at(0).unwrap();
+    for _ in 0..10 {
+        simulation.apply_force(weight, Vec3::new(0.0, -1000.0, 0.0));
+        simulation.step();
+    }
+    assert!(simulation.joint_lines().is_empty(), "the yank didn't break the joint");
+    simulation.restore(&saved);
+    assert_eq!(simulation.joint_lines().len(), 1);
+    for _ in 0..60 {
+        simulation.step();
+    }
+    assert_eq!(Checksum::of(60, &straight.snapshot()), Checksum::of(60, &simulation.snapshot()));
+}
+
+// Owners come from the drags, so going back to before a grab hands the body back.
+#[test]
+fn restore_puts_owners_back() {
+    let mut simulation = pile();
+    let body = simulation.body_at(3).unwrap();
+    let saved = simulation.snapshot();
+    let target = Vec3::new(0.0, 5.0, 0.0);
+    simulation.drag(2, body, target, 40.0, 8.0);
+    simulation.drag(1, body, target, 40.0, 8.0);
+    assert_eq!(simulation.meta(body).unwrap().owner, Some(1));
+    simulation.release(1);
+    assert_eq!(simulation.meta(body).unwrap().owner, Some(2));
+    simulation.restore(&saved);
+    assert_eq!(simulation.meta(body).unwrap().owner, None);
+    assert_eq!(simulation.by_net_id(simulation.meta(body).unwrap().net_id()), Some(body));
+}
@@ -0,0 +1,51 @@
+extern crate rust_network;
+
+use rust_network::config::PhysicsConfig;
+use rust_network::simulation::Simulation;
+use rust_network::scene::Scene;
+use rust_network::vec::Vec3;
+
+const HEIGHT: usize = 10;
+const SECONDS: f32 = 10.0;
+
+// With one contact per pair each box balances on a single point and the tower leans over
+// within a few seconds. With the default max_contacts it should just stand there.
+#[test]
+fn tower_stays_upright() {
+    let config = PhysicsConfig::default();
+    let mut simulation = Simulation::init(&config);
+    for i in 0..HEIGHT {
+        simulation.create_cube(1.0, Vec3::new(0.5, 0.5, 0.5), Vec3::new(0.0, 0.5 + i as f32, 0.0));
+    }
+    simulation.toggle_pause();
+    for _ in 0..(SECONDS / config.step_size) as usize {
+        simulation.step();
+    }
+
+    for (i, (body, _)) in simulation.bodies().enumerate() {
+        let p = simulation.position(body);
+        let drift = (p.x * p.x + p.z * p.z).sqrt();
+        assert!(drift < 0.1, "box {} slid {} sideways to {:?}", i, drift, (p.x, p.y, p.z));
+        assert!((p.y - (0.5 + i as f32)).abs() < 0.1, "box {} is at height {}, not {}", i, p.y, 0.5 + i as f32);
+    }
+}
+
+// Static boxes sitting on the ground plane touch it, with nothing to move. Stepping them used to
+// make contacts with no body on either side and panic collecting collision events.
+#[test]
+fn static_boxes_on_the_ground_step() {
+    let scenes = [
+        r#"{ "statics": [{ "type": "box", "size": [1, 1, 1], "position": [0, 0.5, 0] }] }"#,
+        include_str!("../scenes/arena.json"),
+        include_str!("../scenes/materials.json"),
+    ];
+    for text in scenes.iter() {
+        let config = PhysicsConfig::default();
+        let mut simulation = Simulation::init(&config);
+        Scene::parse(text).unwrap().build(&mut simulation);
+        simulation.toggle_pause();
+        for _ in 0..60 {
+            simulation.step();
+        }
+    }
+}